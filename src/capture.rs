@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::error;
+
+/// Magic number of a pcap file with microsecond timestamps
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+
+/// Link type of Ethernet frames
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Largest TCP payload of a captured segment
+const MAX_SEGMENT: usize = 1460;
+
+/// Synthetic MAC addresses of the server and the peer
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const PEER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+const SYN: u8 = 0x02;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// A captured connection, shared by its reader and writer
+pub(crate) type SharedFlow = Arc<Mutex<Flow>>;
+
+/// Writes the bytes exchanged on all connections to a pcap file, as packets
+/// with synthetic Ethernet, IP and TCP headers, so it opens in Wireshark.
+#[derive(Debug)]
+pub struct Capture {
+    file: Mutex<BufWriter<File>>,
+}
+
+/// A TCP connection being captured, tracking the sequence numbers of both sides
+#[derive(Debug)]
+pub(crate) struct Flow {
+    capture: Arc<Capture>,
+    server: SocketAddr,
+    peer: SocketAddr,
+    server_seq: u32,
+    peer_seq: u32,
+}
+
+impl Capture {
+    /// Creates the pcap file at `path`, writing its header.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Capture> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        file.write_all(&0i32.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&(u16::MAX as u32).to_le_bytes())?;
+        file.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        file.flush()?;
+
+        Ok(Capture {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Starts capturing the connection between `server` and `peer`, with a
+    /// synthetic handshake opening it.
+    pub(crate) fn flow(self: &Arc<Self>, server: SocketAddr, peer: SocketAddr) -> SharedFlow {
+        let mut flow = Flow {
+            capture: self.clone(),
+            server,
+            peer,
+            server_seq: 0,
+            peer_seq: 0,
+        };
+
+        flow.peer_seq = flow.segment(false, SYN, &[]);
+        flow.server_seq = flow.segment(true, SYN | ACK, &[]);
+        flow.segment(false, ACK, &[]);
+
+        Arc::new(Mutex::new(flow))
+    }
+
+    fn write_packet(&self, packet: &[u8]) -> io::Result<()> {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        file.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        file.write_all(packet)?;
+        file.flush()
+    }
+}
+
+impl Flow {
+    /// Captures `data` sent by the server.
+    pub(crate) fn sent(&mut self, data: &[u8]) {
+        for segment in data.chunks(MAX_SEGMENT) {
+            self.segment(true, PSH | ACK, segment);
+        }
+    }
+
+    /// Captures `data` received from the peer.
+    pub(crate) fn received(&mut self, data: &[u8]) {
+        for segment in data.chunks(MAX_SEGMENT) {
+            self.segment(false, PSH | ACK, segment);
+        }
+    }
+
+    /// Writes a TCP segment from the server or the peer, advancing its sequence
+    /// number, which is returned.
+    fn segment(&mut self, from_server: bool, flags: u8, payload: &[u8]) -> u32 {
+        let (src, dst, seq, ack) = match from_server {
+            true => (self.server, self.peer, self.server_seq, self.peer_seq),
+            false => (self.peer, self.server, self.peer_seq, self.server_seq),
+        };
+        let ack = if flags & ACK != 0 { ack } else { 0 };
+
+        let packet = packet(src, dst, seq, ack, flags, payload, from_server);
+        if let Err(err) = self.capture.write_packet(&packet) {
+            error!("could not write to capture: {:}", err);
+        }
+
+        // A SYN takes a sequence number, like a byte of data
+        let len = payload.len() as u32 + u32::from(flags & SYN != 0);
+        let next = seq.wrapping_add(len);
+        match from_server {
+            true => self.server_seq = next,
+            false => self.peer_seq = next,
+        }
+
+        next
+    }
+}
+
+/// Builds an Ethernet frame carrying a TCP segment from `src` to `dst`.
+fn packet(
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+    from_server: bool,
+) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + payload.len());
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let mut frame = Vec::with_capacity(14 + 40 + tcp.len());
+    let (src_mac, dst_mac) = match from_server {
+        true => (SERVER_MAC, PEER_MAC),
+        false => (PEER_MAC, SERVER_MAC),
+    };
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&src_mac);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let pseudo = [&src_ip.octets()[..], &dst_ip.octets(), &[0, 6]].concat();
+            set_tcp_checksum(&mut tcp, &pseudo);
+
+            let mut ip = Vec::with_capacity(20);
+            ip.extend_from_slice(&[0x45, 0]);
+            ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            ip.extend_from_slice(&src_ip.octets());
+            ip.extend_from_slice(&dst_ip.octets());
+            let ip_checksum = checksum(&ip);
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+            frame.extend_from_slice(&0x0800u16.to_be_bytes());
+            frame.extend_from_slice(&ip);
+        }
+        (src_ip, dst_ip) => {
+            let src_ip = to_ipv6(src_ip).octets();
+            let dst_ip = to_ipv6(dst_ip).octets();
+            let pseudo = [&src_ip[..], &dst_ip, &[0, 0, 0, 6]].concat();
+            set_tcp_checksum(&mut tcp, &pseudo);
+
+            frame.extend_from_slice(&0x86ddu16.to_be_bytes());
+            frame.extend_from_slice(&[0x60, 0, 0, 0]);
+            frame.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            frame.extend_from_slice(&[6, 64]);
+            frame.extend_from_slice(&src_ip);
+            frame.extend_from_slice(&dst_ip);
+        }
+    }
+
+    frame.extend_from_slice(&tcp);
+    frame
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Sets the checksum of a TCP segment, given the IP pseudo header without the
+/// segment length.
+fn set_tcp_checksum(tcp: &mut [u8], pseudo: &[u8]) {
+    let len = tcp.len() as u16;
+    let tcp_checksum = checksum(&[pseudo, &len.to_be_bytes(), tcp].concat());
+    tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+}
+
+/// Internet checksum, the one's complement of the one's complement sum
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum();
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_writes_packets_with_sequence_numbers() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let capture = Arc::new(Capture::create(file.path()).unwrap());
+
+        let server: SocketAddr = "127.0.0.1:6020".parse().unwrap();
+        let peer: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let flow = capture.flow(server, peer);
+        flow.lock().unwrap().received(b"Hello");
+        flow.lock().unwrap().sent(b"World!");
+
+        let pcap = std::fs::read(file.path()).unwrap();
+        assert_eq!(&pcap[..4], &PCAP_MAGIC.to_le_bytes());
+
+        // Global header, then the handshake, of packets without payload
+        let frame_len = 14 + 20 + 20;
+        let mut offset = 24 + 3 * (16 + frame_len);
+
+        let received = &pcap[offset + 16..offset + 16 + frame_len + 5];
+        assert_eq!(&received[12..14], &[0x08, 0x00]);
+        assert_eq!(checksum(&received[14..34]), 0);
+        assert_eq!(&received[34..36], &50000u16.to_be_bytes());
+        assert_eq!(&received[38..42], &1u32.to_be_bytes());
+        assert_eq!(&received[received.len() - 5..], b"Hello");
+        offset += 16 + frame_len + 5;
+
+        let sent = &pcap[offset + 16..];
+        assert_eq!(sent.len(), frame_len + 6);
+        assert_eq!(&sent[38..42], &1u32.to_be_bytes());
+        assert_eq!(&sent[42..46], &6u32.to_be_bytes());
+        assert_eq!(&sent[sent.len() - 6..], b"World!");
+    }
+}
@@ -1,6 +1,17 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+use crate::reporter::ReportFormat;
+
+/// Format of the log output
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines, prefixed by the spans of connections and actions
+    Pretty,
+    /// A JSON object per line, with the fields of the spans
+    Json,
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
@@ -13,15 +24,61 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// Log output format
+    #[arg(long, value_enum, default_value = "pretty")]
+    pub log_format: LogFormat,
+
     /// Server host
     #[arg(long, default_value = "127.0.0.1")]
     pub host: String,
 
-    /// Server port
+    /// Server port, any free one with 0
     #[arg(long, default_value = "6020")]
     pub port: u16,
 
-    /// JUnit report path
+    /// Report path, with `{conn}` replaced by the connection index for a file each
     #[arg(short, long, default_value = "result.xml")]
     pub report: PathBuf,
+
+    /// Report formats, written to the report path with the extension of each
+    /// format when there are several
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "junit")]
+    pub report_format: Vec<ReportFormat>,
+
+    /// Time connections get to finish on shutdown, in milliseconds
+    #[arg(long, default_value = "1000")]
+    pub grace_period_ms: u64,
+
+    /// Shut down at the first failure or error
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Capture every byte exchanged to a pcap file
+    #[arg(long)]
+    pub capture: Option<PathBuf>,
+
+    /// Directory to write a hexdump transcript of each connection to
+    #[arg(long)]
+    pub transcript_dir: Option<PathBuf>,
+
+    /// Reload the mapping file whenever it changes, besides on SIGHUP
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Drive connections by hand from a console, instead of the mapped scenario
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Port of an HTTP admin API controlling the server, on the server host
+    #[arg(long)]
+    pub admin_port: Option<u16>,
+
+    /// File to write the bound address to as JSON once accepting connections,
+    /// e.g. with `--port 0`
+    #[arg(long)]
+    pub ready_file: Option<PathBuf>,
+
+    /// Print a `READY <address>` line on stdout once accepting connections
+    #[arg(long)]
+    pub ready_line: bool,
 }
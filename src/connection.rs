@@ -1,23 +1,59 @@
-use anyhow::anyhow;
 use bytes::{Bytes, BytesMut};
-use log::{debug, error, info};
+use socket2::SockRef;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt;
 use std::io::{self, Cursor};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::Notify;
-use tokio::time::{sleep, Instant};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, timeout_at, Instant};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::mapping::{Action, Mapping, MessageAction};
-use crate::reporter::Reporter;
+use crate::capture::SharedFlow;
+use crate::hexdump::{first_mismatch, hex_diff};
+use crate::impairment::{Impairer, Impairment};
+use crate::journal::{FrameStatus, JournalEntry, SharedJournal};
+use crate::mapping::{Action, Mapping, MappingState, MessageAction, Mode, Rule, Scenario};
+use crate::registry::{Progress, Registry, SharedWriter};
+use crate::reporter::{Outcome, Reporter, Traffic};
+use crate::server::ServerConfig;
+use crate::shutdown::Shutdown;
+use crate::transcript::SharedTranscript;
 
 /// Connection holds the interaction between server and peer
 #[derive(Debug)]
 pub(crate) struct Connection {
-    stream: BufWriter<TcpStream>,
+    reader: OwnedReadHalf,
+    writer: SharedWriter,
     buffer: BytesMut,
+    observers: Observers,
+
+    /// Bytes read from the stream so far
+    read_total: u64,
+    /// When the data of each read still in the buffer arrived, by the total
+    /// bytes read up to its end
+    arrivals: VecDeque<(u64, Instant)>,
+    /// When the last message matched was fully received
+    matched_at: Option<Instant>,
+    /// When data not read yet was found ready to be read, while waiting
+    ready_at: Option<Instant>,
+    /// Bytes read from the stream up to the last one recorded in the journal
+    /// as unexpected, so a frame failing several matches is recorded once
+    journaled: u64,
+}
+
+/// Observers of the data exchanged on a connection
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Observers {
+    pub traffic: Arc<Traffic>,
+    pub capture: Option<SharedFlow>,
+    pub transcript: SharedTranscript,
+    pub journal: SharedJournal,
 }
 
 /// ConnHandler handles a single connection logic
@@ -25,7 +61,98 @@ pub(crate) struct Connection {
 pub(crate) struct ConnHandler {
     mapping: Mapping,
     conn: Connection,
-    report_path: String,
+    registry: Arc<Registry>,
+    shutdown: Shutdown,
+    index: usize,
+    peer: Option<SocketAddr>,
+    reporter: Reporter,
+    config: Arc<ServerConfig>,
+
+    /// Names of the actions not finished yet, starting from the current one
+    pending: VecDeque<String>,
+
+    /// When the current action started
+    start_action: Instant,
+
+    /// When the last message was sent, until a message is received after it
+    last_send: Option<Instant>,
+}
+
+/// Write half of a connection, until it is reset, with the impairment applied
+/// to everything sent on it, by its own connection or others
+#[derive(Debug)]
+pub(crate) struct Writer {
+    half: Option<BufWriter<OwnedWriteHalf>>,
+    impairer: Option<Impairer>,
+    observers: Observers,
+}
+
+/// What the peer did while being watched for a period of time
+#[derive(Debug, PartialEq)]
+pub enum PeerEvent {
+    /// Nothing was received
+    Silent,
+
+    /// Given amount of bytes was received
+    Data(usize),
+
+    /// Connection was cleanly closed
+    Closed,
+}
+
+/// Commands to a connection driven from the console
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Control {
+    /// Closes the connection gracefully
+    Close,
+
+    /// Resets the connection, sending a RST
+    Reset,
+
+    /// Runs the scenario of given name, or the top level one
+    Scenario(Option<String>),
+}
+
+/// What a connection driven from the console tells it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ConsoleEvent {
+    /// The connection was accepted from the given peer
+    Connected(Option<SocketAddr>),
+
+    /// Bytes were received from the peer
+    Received(Bytes),
+
+    /// The peer closed the connection
+    ClosedByPeer,
+
+    /// The command was carried out
+    Done(Control),
+
+    /// No scenario of given name is mapped
+    NoScenario(String),
+}
+
+/// Frame read from the stream when any of several messages is expected
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// Index of the expected message the frame matched
+    Matched(usize),
+
+    /// Bytes that match none of the expected messages
+    Unmatched(Bytes),
+}
+
+/// How following a table of rules ended
+#[derive(Debug)]
+enum RulesOutcome<'a> {
+    /// A rule moved to the given state
+    Goto(&'a String),
+
+    /// The deadline was reached
+    TimedOut,
+
+    /// The peer disconnected
+    Closed,
 }
 
 #[derive(Debug)]
@@ -34,7 +161,7 @@ pub enum MessageError {
     Incomplete,
 
     /// Messages do not match
-    NotEqual,
+    NotEqual { expected: Bytes, received: Bytes },
 
     /// Error in buffer
     BufferError,
@@ -44,13 +171,75 @@ pub enum MessageError {
 }
 
 impl Connection {
-    pub fn new(socket: TcpStream) -> Connection {
+    /// Wraps `socket`, applying `impairment` if any to all data sent on it.
+    pub fn new(
+        socket: TcpStream,
+        observers: Observers,
+        impairment: Option<Impairment>,
+    ) -> Connection {
+        // Chunks must not be merged back by Nagle's algorithm
+        if let Err(err) = socket.set_nodelay(true) {
+            debug!("could not set TCP_NODELAY: {:}", err);
+        }
+
+        let (reader, writer) = socket.into_split();
+
         Connection {
-            stream: BufWriter::new(socket),
+            reader,
+            writer: Arc::new(Mutex::new(Writer {
+                half: Some(BufWriter::new(writer)),
+                impairer: impairment.map(Impairer::new),
+                observers: observers.clone(),
+            })),
             buffer: BytesMut::with_capacity(8 * 1024),
+            observers,
+            read_total: 0,
+            arrivals: VecDeque::new(),
+            matched_at: None,
+            ready_at: None,
+            journaled: 0,
         }
     }
 
+    /// Write half of the connection, to be shared with other connections.
+    pub fn writer(&self) -> SharedWriter {
+        self.writer.clone()
+    }
+
+    /// Observers of the data exchanged with the peer.
+    pub fn observers(&self) -> Observers {
+        self.observers.clone()
+    }
+
+    /// Receives whatever bytes come next from the stream, `None` once the peer
+    /// closed it.
+    pub async fn recv_raw(&mut self) -> Result<Option<Bytes>, MessageError> {
+        if self.buffer.is_empty() && 0 == self.read().await? {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buffer.split().freeze()))
+    }
+
+    /// Closes the write half of the connection, so the peer reads EOF.
+    pub async fn close(&mut self) -> Result<(), MessageError> {
+        self.writer
+            .lock()
+            .await
+            .shutdown()
+            .await
+            .map_err(|_| MessageError::BufferError)
+    }
+
+    /// Makes the connection be reset once closed, instead of closed gracefully.
+    pub async fn reset(&mut self) -> Result<(), MessageError> {
+        self.writer
+            .lock()
+            .await
+            .reset()
+            .map_err(|_| MessageError::BufferError)
+    }
+
     /// Receives a message from the stream and checks if match with the one expected.
     pub async fn recv(&mut self, expected_message: &Bytes) -> Result<Option<usize>, MessageError> {
         loop {
@@ -58,21 +247,193 @@ impl Connection {
                 return Ok(Some(len));
             }
 
-            if 0 == self
-                .stream
-                .read_buf(&mut self.buffer)
-                .await
-                .map_err(|_| MessageError::BufferError)?
-            {
+            if 0 == self.read().await? {
                 if self.buffer.is_empty() {
                     return Ok(None);
                 } else {
-                    return Err(MessageError::Other(anyhow!("connection reset by peer")));
+                    // The peer closed the connection after a partial message
+                    return Err(MessageError::NotEqual {
+                        expected: expected_message.clone(),
+                        received: self.buffer.split().freeze(),
+                    });
                 }
             }
         }
     }
 
+    /// Receives the next frame from the stream, matching it against the `expected` messages.
+    ///
+    /// Bytes that can not be the start of any expected message are returned as
+    /// unmatched, up to where one of them could start.
+    pub async fn recv_any(&mut self, expected: &[&Bytes]) -> Result<Option<Frame>, MessageError> {
+        loop {
+            if let Some(frame) = self.check_recv_any(expected) {
+                return Ok(Some(frame));
+            }
+
+            if 0 == self.read().await? {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                } else {
+                    return Ok(Some(Frame::Unmatched(self.buffer.split().freeze())));
+                }
+            }
+        }
+    }
+
+    fn check_recv_any(&mut self, expected: &[&Bytes]) -> Option<Frame> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        debug!("expected any of: {:?}\tbuffer: {:?}", expected, self.buffer);
+
+        if let Some(index) = expected.iter().position(|m| self.buffer.starts_with(m)) {
+            self.matched(expected[index].len());
+            let _ = self.buffer.split_to(expected[index].len());
+            return Some(Frame::Matched(index));
+        }
+
+        if expected.iter().any(|m| m.starts_with(&self.buffer)) {
+            return None;
+        }
+
+        // Resynchronizes on the first offset where an expected message could start
+        let junk = (1..self.buffer.len())
+            .find(|&offset| {
+                let rest = &self.buffer[offset..];
+                expected
+                    .iter()
+                    .any(|m| rest.starts_with(m) || m.starts_with(rest))
+            })
+            .unwrap_or(self.buffer.len());
+
+        Some(Frame::Unmatched(self.buffer.split_to(junk).freeze()))
+    }
+
+    /// Waits until the data received starts with one of the `expected` messages,
+    /// returning its index, without consuming it.
+    ///
+    /// Returns `None` once the data can not be any of them, or the peer disconnects.
+    pub async fn peek_any(&mut self, expected: &[&Bytes]) -> Result<Option<usize>, MessageError> {
+        loop {
+            if let Some(index) = expected.iter().position(|m| self.buffer.starts_with(m)) {
+                return Ok(Some(index));
+            }
+
+            if !self.buffer.is_empty() && !expected.iter().any(|m| m.starts_with(&self.buffer)) {
+                return Ok(None);
+            }
+
+            if 0 == self.read().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Waits for `duration` without reading from the stream, noting when data
+    /// arrives meanwhile.
+    pub async fn wait(&mut self, duration: Duration) {
+        let _ = timeout(duration, self.note_arrival()).await;
+    }
+
+    /// Notes when data is ready to be read, without reading it, as the time it
+    /// arrived. Never completes, to be raced against whatever keeps the
+    /// connection from reading.
+    pub async fn note_arrival(&mut self) {
+        let mut byte = [0u8; 1];
+        if self.ready_at.is_none() {
+            // Peeking leaves the data in the socket, filling its receive window
+            if let Ok(1..) = self.reader.peek(&mut byte).await {
+                self.ready_at = Some(Instant::now());
+            }
+        }
+
+        std::future::pending().await
+    }
+
+    /// Data received but not consumed, along with whatever is ready to be read
+    /// without waiting.
+    pub fn leftover(&mut self) -> Bytes {
+        let mut chunk = [0u8; 4096];
+        while let Ok(len @ 1..) = self.reader.try_read(&mut chunk) {
+            self.buffer.extend_from_slice(&chunk[..len]);
+            self.observers.received(&chunk[..len]);
+        }
+
+        self.buffer.split().freeze()
+    }
+
+    /// Bytes read from the stream and consumed so far, the offset the buffer starts at.
+    pub fn consumed(&self) -> u64 {
+        self.read_total - self.buffer.len() as u64
+    }
+
+    /// The part of `data`, received from offset `start`, not yet recorded in
+    /// the journal, marking it as recorded.
+    pub fn unjournaled(&mut self, start: u64, data: &Bytes) -> Bytes {
+        let end = start + data.len() as u64;
+        let recorded = self.journaled.clamp(start, end) - start;
+        self.journaled = self.journaled.max(end);
+
+        data.slice(recorded as usize..)
+    }
+
+    /// When the last message matched was fully received, if any.
+    pub fn matched_at(&self) -> Option<Instant> {
+        self.matched_at
+    }
+
+    /// Watches the stream for `duration`, returning at the first thing the peer does.
+    ///
+    /// Data received is kept in the buffer, to be matched by next actions.
+    pub async fn watch(&mut self, duration: Duration) -> Result<PeerEvent, MessageError> {
+        if !self.buffer.is_empty() {
+            return Ok(PeerEvent::Data(self.buffer.len()));
+        }
+
+        match timeout(duration, self.read()).await {
+            Err(_) => Ok(PeerEvent::Silent),
+            Ok(Ok(0)) => Ok(PeerEvent::Closed),
+            Ok(Ok(len)) => Ok(PeerEvent::Data(len)),
+            Ok(Err(err)) => Err(err),
+        }
+    }
+
+    /// Reads more data from the stream into the buffer, returning how much.
+    async fn read(&mut self) -> Result<usize, MessageError> {
+        let len = self
+            .reader
+            .read_buf(&mut self.buffer)
+            .await
+            .map_err(|_| MessageError::BufferError)?;
+        self.observers
+            .received(&self.buffer[self.buffer.len() - len..]);
+
+        if len > 0 {
+            let at = self.ready_at.take().unwrap_or_else(Instant::now);
+            self.read_total += len as u64;
+            self.arrivals.push_back((self.read_total, at));
+        }
+        // Reads whose data was all consumed are no longer needed
+        let consumed = self.read_total - self.buffer.len() as u64;
+        while self.arrivals.len() > 1 && self.arrivals[0].0 <= consumed {
+            self.arrivals.pop_front();
+        }
+
+        Ok(len)
+    }
+
+    /// Marks the first `len` bytes of the buffer as a matched message, noting
+    /// when its last byte arrived.
+    fn matched(&mut self, len: usize) {
+        let end = self.read_total - (self.buffer.len() - len) as u64;
+        self.matched_at = self
+            .arrivals
+            .iter()
+            .find(|(read_total, _)| *read_total >= end)
+            .map(|(_, at)| *at);
+    }
+
     fn check_recv(&mut self, expected_message: &Bytes) -> Result<Option<usize>, MessageError> {
         let buf_cursor = Cursor::new(&self.buffer[..]);
 
@@ -87,16 +448,27 @@ impl Connection {
         debug!("expected: {:?}\tbuffer: {:?}", expected_message, recv);
 
         if *expected_message == recv.slice(..expected_len) {
+            self.matched(expected_len);
             self.buffer = self.buffer.split_off(expected_len);
             return Ok(Some(expected_len));
         }
 
-        Err(MessageError::NotEqual)
+        Err(MessageError::NotEqual {
+            expected: expected_message.clone(),
+            received: recv.slice(..expected_len),
+        })
     }
 
-    /// Sends message to the stream.
-    pub async fn send(&mut self, msg_name: &String, msg: &Bytes) -> Result<(), MessageError> {
-        self.write_message(msg)
+    /// Sends message to the stream, split in chunks at the given `split_points`
+    /// and waiting `delay` between them.
+    pub async fn send(
+        &mut self,
+        msg_name: &String,
+        msg: &Bytes,
+        split_points: &[usize],
+        delay: Duration,
+    ) -> Result<(), MessageError> {
+        self.write_chunks(msg, split_points, delay)
             .await
             .map_err(|_| MessageError::BufferError)?;
         info!("send '{:}': {:#?}", msg_name, msg);
@@ -104,77 +476,905 @@ impl Connection {
         Ok(())
     }
 
+    /// Sends message to the stream, with the framing options of `action`.
+    pub async fn send_framed(
+        &mut self,
+        action: &MessageAction,
+        msg: &Bytes,
+    ) -> Result<(), MessageError> {
+        self.send(
+            &action.message,
+            msg,
+            &action.split_points(msg.len()),
+            Duration::from_millis(action.chunk_delay_ms),
+        )
+        .await
+    }
+
     pub async fn write_message(&mut self, message: &Bytes) -> io::Result<()> {
-        self.stream.write_all(message).await?;
-        self.stream.flush().await
+        // The impairment may hold the write for a while
+        let writer = self.writer.clone();
+        tokio::select! {
+            written = write_to(&writer, message) => written,
+            _ = self.note_arrival() => unreachable!(),
+        }
+    }
+
+    /// Writes each chunk of `message` with its own flush, so they leave in separate segments.
+    pub async fn write_chunks(
+        &mut self,
+        message: &Bytes,
+        split_points: &[usize],
+        delay: Duration,
+    ) -> io::Result<()> {
+        let mut start = 0;
+
+        for &end in split_points.iter().chain(std::iter::once(&message.len())) {
+            if start != 0 && !delay.is_zero() {
+                self.wait(delay).await;
+            }
+            debug!("send chunk [{}..{}]", start, end);
+            self.write_message(&message.slice(start..end)).await?;
+            start = end;
+        }
+
+        Ok(())
+    }
+}
+
+/// Names the test suite of a connection by its accept order and peer address
+fn suite_name(mapping_name: &str, index: usize, peer: Option<SocketAddr>) -> String {
+    match peer {
+        Some(peer) => format!("{} #{} ({})", mapping_name, index, peer),
+        None => format!("{} #{}", mapping_name, index),
+    }
+}
+
+impl Observers {
+    /// Records data written to the peer.
+    pub fn sent(&self, data: &[u8]) {
+        self.traffic.sent(data.len());
+        if let Some(flow) = &self.capture {
+            flow.lock().unwrap().sent(data);
+        }
+        self.transcript.lock().unwrap().write(data);
+    }
+
+    /// Records data read from the peer.
+    pub fn received(&self, data: &[u8]) {
+        self.traffic.received(data.len());
+        if let Some(flow) = &self.capture {
+            flow.lock().unwrap().received(data);
+        }
+        self.transcript.lock().unwrap().read(data);
+    }
+}
+
+/// Writes `data` to a shared write half, flushing it, under the impairment of
+/// its connection.
+pub(crate) async fn write_to(writer: &SharedWriter, data: &[u8]) -> io::Result<()> {
+    writer.lock().await.write(data).await
+}
+
+impl Writer {
+    /// Writes `data`, flushing it, under the impairment if any.
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let half = self
+            .half
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let Some(impairer) = self.impairer.as_mut() else {
+            return write_slice(half, &self.observers, data).await;
+        };
+
+        sleep(impairer.latency()).await;
+
+        for slice in data.chunks(impairer.slice_len(data.len())) {
+            if let Some(stall) = impairer.stall() {
+                debug!("stalling for {:?}", stall);
+                sleep(stall).await;
+            }
+
+            write_slice(half, &self.observers, slice).await?;
+            sleep(impairer.transmit_time(slice.len())).await;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the write half, so the peer reads EOF.
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.half()?.shutdown().await
+    }
+
+    /// Resets the connection once its read half is dropped too. The write half
+    /// is forgotten, as dropping it would close it gracefully first.
+    fn reset(&mut self) -> io::Result<()> {
+        if let Some(half) = self.half.take() {
+            let half = half.into_inner();
+            SockRef::from(half.as_ref()).set_linger(Some(Duration::ZERO))?;
+            half.forget();
+        }
+
+        Ok(())
+    }
+
+    fn half(&mut self) -> io::Result<&mut BufWriter<OwnedWriteHalf>> {
+        self.half
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
     }
 }
 
+async fn write_slice(
+    half: &mut BufWriter<OwnedWriteHalf>,
+    observers: &Observers,
+    slice: &[u8],
+) -> io::Result<()> {
+    half.write_all(slice).await?;
+    half.flush().await?;
+    observers.sent(slice);
+
+    Ok(())
+}
+
 impl ConnHandler {
-    pub fn new(mapping: Mapping, socket: TcpStream, report_path: String) -> ConnHandler {
+    pub fn new(
+        mapping: Mapping,
+        socket: TcpStream,
+        index: usize,
+        registry: Arc<Registry>,
+        shutdown: Shutdown,
+        config: Arc<ServerConfig>,
+    ) -> ConnHandler {
+        let peer = socket.peer_addr().ok();
+        let local = socket.local_addr().ok();
+        let mut observers = Observers::default();
+        if let (Some(capture), Some(local), Some(peer)) = (&config.capture, local, peer) {
+            observers.capture = Some(capture.flow(local, peer));
+        }
+
+        let state = mapping.state.try_read().unwrap();
+        let conn = Connection::new(socket, observers, state.impairment.clone());
+        registry.register(index, peer, conn.writer(), conn.observers());
+        let mut reporter = Reporter::new(&suite_name(&state.mapping_name, index, peer));
+        reporter.set_connection(index, peer, local, conn.observers());
+        drop(state);
+
         ConnHandler {
             mapping,
-            conn: Connection::new(socket),
-            report_path,
+            conn,
+            registry,
+            shutdown,
+            index,
+            peer,
+            reporter,
+            config,
+            pending: VecDeque::new(),
+            start_action: Instant::now(),
+            last_send: None,
+        }
+    }
+
+    /// Handles the connection until its scenario is done, fails, or the grace
+    /// period after a server shutdown elapses, reporting unfinished actions if so.
+    pub async fn run(&mut self) -> Result<(), MessageError> {
+        let mut shutdown = self.shutdown.clone();
+
+        let result = tokio::select! {
+            result = self.run_scenario() => Some(result),
+            _ = shutdown.expired() => None,
+        };
+
+        let result = match result {
+            Some(Ok(())) => Ok(()),
+            Some(Err(err)) => {
+                let message = err.to_string();
+                self.abort(
+                    "message_error",
+                    &message,
+                    &format!("not reached, earlier action failed: {}", message),
+                );
+                Err(err)
+            }
+            None => {
+                warn!("connection {:} interrupted by server shutdown", self.index);
+                let reason = "interrupted by server shutdown";
+                self.abort("interrupted", reason, reason);
+                Ok(())
+            }
+        };
+
+        self.journal_leftover();
+        result
+    }
+
+    /// Results of this connection
+    pub fn reporter(&self) -> &Reporter {
+        &self.reporter
+    }
+
+    async fn run_scenario(&mut self) -> Result<(), MessageError> {
+        let state = self.mapping.state.clone();
+        let mapping = state.try_read().unwrap();
+
+        if self.config.interactive {
+            return self.run_interactive(&mapping).await;
+        }
+
+        let scenario = self.select_scenario(&mapping).await?;
+        self.run_mode(&mapping, scenario).await
+    }
+
+    /// Runs a scenario according to its mode.
+    async fn run_mode(
+        &mut self,
+        mapping: &MappingState,
+        scenario: &Scenario,
+    ) -> Result<(), MessageError> {
+        if let Some(group) = &scenario.group {
+            self.registry.join(self.index, group);
+        }
+
+        match scenario.mode {
+            Mode::Script => self.run_script(mapping, scenario).await,
+            Mode::Stub => self.run_stub(mapping, scenario).await,
+            Mode::States => self.run_states(mapping, scenario).await,
+        }
+    }
+
+    /// Lets the console drive the connection, printing each frame received,
+    /// until it closes or resets the connection, or switches it to a scenario.
+    async fn run_interactive(&mut self, mapping: &MappingState) -> Result<(), MessageError> {
+        let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+        self.registry.set_control(self.index, control_tx);
+        self.progress(|progress| progress.step = Some("interactive".to_string()));
+        self.tell_console(ConsoleEvent::Connected(self.peer));
+
+        loop {
+            tokio::select! {
+                data = self.conn.recv_raw() => match data? {
+                    Some(data) => self.tell_console(ConsoleEvent::Received(data)),
+                    None => {
+                        self.tell_console(ConsoleEvent::ClosedByPeer);
+                        return Ok(());
+                    }
+                },
+                control = control_rx.recv() => {
+                    let control = control.unwrap_or(Control::Close);
+                    let scenario = match &control {
+                        Control::Close => {
+                            self.tell_console(ConsoleEvent::Done(control));
+                            return self.conn.close().await;
+                        }
+                        Control::Reset => {
+                            self.tell_console(ConsoleEvent::Done(control));
+                            return self.conn.reset().await;
+                        }
+                        Control::Scenario(None) => &mapping.scenario,
+                        Control::Scenario(Some(name)) => match mapping.scenarios.get(name) {
+                            Some(scenario) => {
+                                self.reporter.set_scenario(name);
+                                self.progress(|progress| progress.scenario = Some(name.clone()));
+                                scenario
+                            }
+                            None => {
+                                self.tell_console(ConsoleEvent::NoScenario(name.clone()));
+                                continue;
+                            }
+                        },
+                    };
+
+                    self.tell_console(ConsoleEvent::Done(control));
+                    return self.run_mode(mapping, scenario).await;
+                }
+            }
+        }
+    }
+
+    /// Tells the console what happened on the connection, if it listens.
+    fn tell_console(&self, event: ConsoleEvent) {
+        self.registry.to_console(self.index, event);
+    }
+
+    /// Records the action in progress as an error and the ones not reached as
+    /// skipped. Without any action in progress, the connection itself errored.
+    fn abort(&mut self, error_type: &str, message: &str, skip_reason: &str) {
+        let current = self
+            .pending
+            .pop_front()
+            .unwrap_or_else(|| "connection".to_string());
+        self.reporter
+            .error(&current, self.start_action.elapsed(), error_type, message);
+
+        self.skip_pending(skip_reason);
+    }
+
+    /// Records the actions not reached as skipped.
+    fn skip_pending(&mut self, reason: &str) {
+        for name in std::mem::take(&mut self.pending) {
+            self.reporter.skipped(&name, reason);
+        }
+    }
+
+    /// Records a received frame in the journal, as part of the next step.
+    fn journal(&self, message: Option<&str>, data: &Bytes, status: FrameStatus) {
+        let entry = JournalEntry {
+            step: self.reporter.step_count() + 1,
+            message: message.map(String::from),
+            data: data.clone(),
+            status,
+        };
+
+        self.conn.observers.journal.lock().unwrap().record(entry);
+    }
+
+    /// Records the data received that no action consumed as unmatched, after
+    /// the last step, but for what was already recorded as mismatched.
+    fn journal_leftover(&mut self) {
+        let start = self.conn.consumed();
+        let leftover = self.conn.leftover();
+        let leftover = self.conn.unjournaled(start, &leftover);
+        if leftover.is_empty() {
+            return;
+        }
+        warn!("{} bytes received were never consumed", leftover.len());
+
+        let entry = JournalEntry {
+            step: self.reporter.step_count(),
+            message: None,
+            data: leftover,
+            status: FrameStatus::Unmatched,
+        };
+        self.conn.observers.journal.lock().unwrap().record(entry);
+    }
+
+    /// Publishes where this connection is in its scenario.
+    fn progress(&self, update: impl FnOnce(&mut Progress)) {
+        self.registry.progress(self.index, update);
+    }
+
+    /// Shuts the server down once anything fails, in fail-fast mode.
+    fn fail_fast(&self) -> bool {
+        if !self.config.fail_fast || self.reporter.summary().outcome() == Outcome::Passed {
+            return false;
+        }
+
+        info!("failing fast, notifying shutdown");
+        self.config.shutdown_notify.notify_waiters();
+        true
+    }
+
+    /// Selects the scenario of this connection from the mapped selectors,
+    /// falling back to the top level one when none matches.
+    async fn select_scenario<'a>(
+        &mut self,
+        mapping: &'a MappingState,
+    ) -> Result<&'a Scenario, MessageError> {
+        let first_messages: Vec<&String> = mapping
+            .select
+            .iter()
+            .filter_map(|selector| selector.first_message.as_ref())
+            .collect();
+        let mut first_message = None;
+
+        for selector in &mapping.select {
+            if !selector.matches_peer(self.index, self.peer) {
+                continue;
+            }
+
+            if let Some(expected) = &selector.first_message {
+                if first_message.is_none() {
+                    let candidates: Vec<&Bytes> = first_messages
+                        .iter()
+                        .map(|name| &mapping.name_to_message[*name])
+                        .collect();
+                    let peek = self.conn.peek_any(&candidates);
+                    let index = match selector.first_message_timeout_ms {
+                        Some(ms) => match timeout(Duration::from_millis(ms), peek).await {
+                            Ok(index) => index?,
+                            // Whatever was received is kept, for the next selectors
+                            Err(_) => {
+                                info!(
+                                    "no first message within {} ms for scenario '{}'",
+                                    ms, selector.scenario
+                                );
+                                continue;
+                            }
+                        },
+                        None => peek.await?,
+                    };
+                    first_message = Some(index.map(|index| first_messages[index]));
+                }
+
+                if first_message != Some(Some(expected)) {
+                    continue;
+                }
+            }
+
+            info!(
+                "connection {:} from {:?} selected scenario '{:}'",
+                self.index, self.peer, selector.scenario
+            );
+            self.reporter.set_scenario(&selector.scenario);
+            self.progress(|progress| progress.scenario = Some(selector.scenario.clone()));
+            Span::current().record("scenario", selector.scenario.as_str());
+            return Ok(&mapping.scenarios[&selector.scenario]);
+        }
+
+        Ok(&mapping.scenario)
+    }
+
+    /// Executes the mapped actions, in order.
+    async fn run_script(
+        &mut self,
+        mapping: &MappingState,
+        scenario: &Scenario,
+    ) -> Result<(), MessageError> {
+        self.execute(mapping, &scenario.message_actions).await
+    }
+
+    /// Replies to each received message following the mapped rules, until the peer disconnects.
+    async fn run_stub(
+        &mut self,
+        mapping: &MappingState,
+        scenario: &Scenario,
+    ) -> Result<(), MessageError> {
+        self.follow_rules(
+            mapping,
+            &scenario.rules,
+            scenario.default_rule.as_ref(),
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves between the mapped states, starting from the initial one, until a
+    /// state has nowhere else to go or the peer disconnects.
+    async fn run_states(
+        &mut self,
+        mapping: &MappingState,
+        scenario: &Scenario,
+    ) -> Result<(), MessageError> {
+        let mut current = &scenario.initial_state;
+
+        loop {
+            info!("entering state '{:}'", current);
+            let state = &scenario.states[current];
+            self.reporter.set_classname(Some(current));
+            self.progress(|progress| progress.state = Some(current.clone()));
+            let span = info_span!("state", state = %current);
+
+            self.execute(mapping, &state.actions)
+                .instrument(span.clone())
+                .await?;
+
+            let next = if state.rules.is_empty() {
+                state.next.as_ref()
+            } else {
+                let deadline = state
+                    .timeout_ms
+                    .map(|timeout| Instant::now() + Duration::from_millis(timeout));
+
+                match self
+                    .follow_rules(mapping, &state.rules, state.default.as_ref(), deadline)
+                    .instrument(span)
+                    .await?
+                {
+                    RulesOutcome::Goto(target) => Some(target),
+                    RulesOutcome::TimedOut => {
+                        info!("state '{:}' timed out", current);
+                        state.next.as_ref()
+                    }
+                    RulesOutcome::Closed => None,
+                }
+            };
+
+            match next {
+                Some(target) => current = target,
+                None => break,
+            }
         }
+        info!("finished in state '{:}'", current);
+
+        Ok(())
     }
 
-    pub async fn run(&mut self, notify: Arc<Notify>) -> Result<(), MessageError> {
-        let mapping = self.mapping.state.try_read().unwrap();
+    /// Executes the given actions, in order, recording their results.
+    async fn execute(
+        &mut self,
+        mapping: &MappingState,
+        actions: &VecDeque<MessageAction>,
+    ) -> Result<(), MessageError> {
+        let mut swapped: Option<(&MessageAction, Bytes)> = None;
+        self.pending = actions.iter().map(MessageAction::name).collect();
 
-        let mut reporter = Reporter::new(&mapping.mapping_name);
+        for (step, next_action) in actions.iter().enumerate() {
+            self.start_action = Instant::now();
+            self.progress(|progress| progress.step = Some(next_action.name()));
+            let span = info_span!(
+                "action",
+                step,
+                kind = ?next_action.execute,
+                msg = %next_action.message
+            );
 
-        for next_action in &mapping.message_actions {
-            let MessageAction {
-                message,
-                execute,
-                wait_for,
-            } = next_action;
-            let msg_value = &mapping.name_to_message[message];
+            let open = self
+                .execute_action(mapping, next_action, &mut swapped)
+                .instrument(span)
+                .await?;
+            self.pending.pop_front();
 
-            let start_action = Instant::now();
+            if !open {
+                self.skip_pending("not reached, connection closed");
+                return Ok(());
+            }
 
-            if *wait_for != 0 {
-                info!("waiting for {} seconds", *wait_for);
-                sleep(Duration::from_secs(*wait_for)).await;
+            if self.fail_fast() {
+                self.skip_pending("server failing fast");
+                return Ok(());
             }
+        }
+
+        if let Some((action, value)) = swapped.take() {
+            self.start_action = Instant::now();
+            self.conn.send_framed(action, &value).await?;
+            self.last_send = Some(Instant::now());
+            self.reporter
+                .sucess(&action.name(), self.start_action.elapsed());
+            self.pending.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single action, recording its result.
+    ///
+    /// Returns `false` once the peer closed the connection, so no further
+    /// action can run.
+    async fn execute_action<'a>(
+        &mut self,
+        mapping: &MappingState,
+        next_action: &'a MessageAction,
+        swapped: &mut Option<(&'a MessageAction, Bytes)>,
+    ) -> Result<bool, MessageError> {
+        let MessageAction {
+            message,
+            execute,
+            wait_for,
+            ..
+        } = next_action;
+        let msg_value = &mapping.name_to_message[message];
 
-            match execute {
-                Action::Shutdown => {
-                    info!("notifying shutdown");
-                    notify.notify_waiters()
+        let start_action = self.start_action;
+
+        if *wait_for != 0 {
+            info!("waiting for {} seconds", *wait_for);
+            self.conn.wait(Duration::from_secs(*wait_for)).await;
+        }
+
+        match execute {
+            Action::Shutdown => {
+                info!("notifying shutdown");
+                self.config.shutdown_notify.notify_waiters();
+                self.reporter.sucess("Shutdown", start_action.elapsed());
+            }
+            Action::Send => {
+                let msg_value = mapping.outgoing_message(next_action);
+                if next_action.is_swapped() {
+                    info!("message '{:}' will be sent after the next one", message);
+                    // Still pending until sent, so skipped if never reached
+                    self.pending.push_back(next_action.name());
+                    *swapped = Some((next_action, msg_value));
+                } else {
+                    self.conn.send_framed(next_action, &msg_value).await?;
+                    self.last_send = Some(Instant::now());
+                    self.reporter.sucess(message, start_action.elapsed());
+                    if let Some((action, value)) = swapped.take() {
+                        // In progress now, in place of this one
+                        self.pending.pop_back();
+                        if let Some(current) = self.pending.front_mut() {
+                            *current = action.name();
+                        }
+                        self.conn.send_framed(action, &value).await?;
+                        self.last_send = Some(Instant::now());
+                        self.reporter.sucess(&action.name(), start_action.elapsed());
+                    }
                 }
-                Action::Send => self.conn.send(message, msg_value).await?,
-                Action::Recv => {
-                    match self.conn.recv(msg_value).await {
-                        Ok(Some(_)) => {
-                            info!("message '{:}' was recv correctly", message);
-                            reporter.sucess(message, start_action.elapsed());
+            }
+            Action::Recv => {
+                let start = self.conn.consumed();
+                match self.conn.recv(msg_value).await {
+                    Ok(Some(_)) => {
+                        let latency = self.latency();
+                        self.journal(Some(message), msg_value, FrameStatus::Matched);
+                        info!("message '{:}' was recv correctly", message);
+                        self.reporter.sucess(message, start_action.elapsed());
+                        if let Some(latency) = latency {
+                            self.reporter.set_latency(latency);
                         }
-                        Ok(None) => {
-                            error!("message '{:}' was not recv correctly", message);
-                            reporter.failure(
-                                message,
-                                start_action.elapsed(),
-                                "recv_error",
-                                "message not recv correctly",
-                            );
+                    }
+                    Ok(None) => {
+                        error!("connection closed before message '{:}' was recv", message);
+                        self.reporter.failure(
+                            message,
+                            start_action.elapsed(),
+                            "recv_error",
+                            "connection closed before message was recv",
+                        );
+                        return Ok(false);
+                    }
+                    Err(MessageError::NotEqual { expected, received }) => {
+                        let diff = hex_diff(&expected, &received);
+                        // Bytes failing a match are left for the next actions,
+                        // which may fail on them again
+                        let unjournaled = self.conn.unjournaled(start, &received);
+                        if !unjournaled.is_empty() {
+                            self.journal(Some(message), &unjournaled, FrameStatus::Mismatched);
                         }
-                        Err(e) => {
-                            error!("{:}", e);
-                            reporter.error(
-                                message,
-                                start_action.elapsed(),
-                                "message_error",
-                                format!("{:}", e).as_str(),
-                            );
+                        error!("message '{:}' was not recv correctly\n{:}", message, diff);
+                        self.reporter.failure_with_output(
+                            message,
+                            start_action.elapsed(),
+                            "recv_error",
+                            "message not recv correctly",
+                            &diff,
+                        );
+                    }
+                    Err(e) => {
+                        error!("{:}", e);
+                        self.reporter.error(
+                            message,
+                            start_action.elapsed(),
+                            "message_error",
+                            format!("{:}", e).as_str(),
+                        );
+                    }
+                };
+            }
+            Action::ExpectSilence | Action::ExpectClose => {
+                let name = next_action.name();
+                let duration = Duration::from_millis(next_action.duration_ms);
+                let expected = match execute {
+                    Action::ExpectSilence => PeerEvent::Silent,
+                    _ => PeerEvent::Closed,
+                };
+
+                match self.conn.watch(duration).await {
+                    Ok(event) if event == expected => {
+                        info!("'{:}' met: {:?} for {:?}", name, event, duration);
+                        self.reporter.sucess(&name, start_action.elapsed());
+                    }
+                    Ok(event) => {
+                        error!("'{:}' not met: {:?} for {:?}", name, event, duration);
+                        self.reporter.failure(
+                            &name,
+                            start_action.elapsed(),
+                            "expectation_error",
+                            format!("expected {:?}, got {:?}", expected, event).as_str(),
+                        );
+                    }
+                    Err(e) => {
+                        error!("{:}", e);
+                        self.reporter.error(
+                            &name,
+                            start_action.elapsed(),
+                            "message_error",
+                            format!("{:}", e).as_str(),
+                        );
+                    }
+                };
+            }
+            Action::PauseRead => {
+                // Data is only read from the socket by actions expecting it,
+                // so the client fills the receive window while we wait here,
+                // or else until the next action reading
+                match next_action.timeout() {
+                    Some(duration) => {
+                        info!("pausing reads for {:?}", duration);
+                        self.conn.wait(duration).await;
+                    }
+                    None => info!("pausing reads until the next action reading"),
+                }
+                self.reporter
+                    .sucess(&next_action.name(), start_action.elapsed());
+            }
+            Action::Broadcast => {
+                let msg_value = mapping.outgoing_message(next_action);
+                let entries = self.registry.entries(next_action.group.as_deref());
+                info!(
+                    "broadcast '{:}' to {} connections: {:#?}",
+                    message,
+                    entries.len(),
+                    msg_value
+                );
+
+                // Each connection is written at its own pace, under its impairment
+                let mut writes = JoinSet::new();
+                for (index, entry) in entries {
+                    let msg_value = msg_value.clone();
+                    writes.spawn(async move {
+                        let written = write_to(&entry.writer, &msg_value).await;
+                        (index, entry.peer, written)
+                    });
+                }
+
+                let mut failed = BTreeMap::new();
+                while let Some(Ok((index, peer, written))) = writes.join_next().await {
+                    if let Err(err) = written {
+                        error!(
+                            "broadcast to connection {:} from {:?} failed: {:}",
+                            index, peer, err
+                        );
+                        failed.insert(index, format!("connection {}: {}", index, err));
+                    }
+                }
+
+                if failed.is_empty() {
+                    self.reporter.sucess(message, start_action.elapsed());
+                } else {
+                    self.reporter.error(
+                        message,
+                        start_action.elapsed(),
+                        "broadcast_error",
+                        &failed.into_values().collect::<Vec<String>>().join(", "),
+                    );
+                }
+            }
+            Action::Signal => {
+                info!("raising signal '{:}'", next_action.signal);
+                self.mapping.signals.raise(&next_action.signal);
+                self.reporter
+                    .sucess(&next_action.name(), start_action.elapsed());
+            }
+            Action::WaitFor | Action::Barrier => {
+                let name = next_action.name();
+                let signals = &self.mapping.signals;
+                let parties = next_action.parties.unwrap_or(2);
+                let conn = &mut self.conn;
+                let waiting = async {
+                    let signaled = async {
+                        match execute {
+                            Action::Barrier => signals.barrier(&next_action.signal, parties).await,
+                            _ => signals.wait(&next_action.signal, 1).await,
                         }
                     };
+                    tokio::select! {
+                        _ = signaled => {}
+                        _ = conn.note_arrival() => unreachable!(),
+                    }
+                };
+                let reached = match next_action.timeout() {
+                    Some(duration) => timeout(duration, waiting).await.is_ok(),
+                    None => {
+                        waiting.await;
+                        true
+                    }
+                };
+
+                if reached {
+                    info!("'{:}' reached", name);
+                    self.reporter.sucess(&name, start_action.elapsed());
+                } else {
+                    error!("'{:}' timed out", name);
+                    let message = match execute {
+                        // Others waiting in the current generation, and this one
+                        Action::Barrier => format!(
+                            "{} of {} parties reached the barrier",
+                            signals.arrivals(&next_action.signal) % parties + 1,
+                            parties
+                        ),
+                        _ => format!(
+                            "signal raised {} of 1 times",
+                            signals.count(&next_action.signal)
+                        ),
+                    };
+                    self.reporter
+                        .failure(&name, start_action.elapsed(), "signal_error", &message);
                 }
-                Action::Unknown => unimplemented!(),
+            }
+            Action::Unknown => unimplemented!(),
+        };
+
+        Ok(true)
+    }
+
+    /// Replies to received messages following `rules`, until a rule moves to
+    /// another state, the `deadline` is reached or the peer disconnects.
+    async fn follow_rules<'a>(
+        &mut self,
+        mapping: &MappingState,
+        rules: &'a [Rule],
+        default_rule: Option<&'a Rule>,
+        deadline: Option<Instant>,
+    ) -> Result<RulesOutcome<'a>, MessageError> {
+        let triggers: Vec<&Bytes> = rules
+            .iter()
+            .map(|rule| &mapping.name_to_message[&rule.when])
+            .collect();
+        self.progress(|progress| progress.step = Some("following rules".to_string()));
+
+        loop {
+            let start_frame = Instant::now();
+
+            let frame = match deadline {
+                Some(deadline) => match timeout_at(deadline, self.conn.recv_any(&triggers)).await {
+                    Ok(frame) => frame?,
+                    Err(_) => return Ok(RulesOutcome::TimedOut),
+                },
+                None => self.conn.recv_any(&triggers).await?,
             };
+
+            let rule = match frame {
+                None => return Ok(RulesOutcome::Closed),
+                Some(Frame::Matched(index)) => {
+                    let latency = self.latency();
+                    let rule = &rules[index];
+                    info!("message '{:}' was recv", rule.when);
+                    self.journal(Some(&rule.when), triggers[index], FrameStatus::Matched);
+                    self.reporter.sucess(&rule.when, start_frame.elapsed());
+                    if let Some(latency) = latency {
+                        self.reporter.set_latency(latency);
+                    }
+                    Some(rule)
+                }
+                Some(Frame::Unmatched(frame)) => {
+                    self.journal(None, &frame, FrameStatus::Unmatched);
+                    if default_rule.is_some() {
+                        info!(
+                            "unmatched frame recv, following the default rule: {:?}",
+                            frame
+                        );
+                        self.reporter.sucess("default", start_frame.elapsed());
+                    } else {
+                        error!("unmatched frame recv: {:?}", frame);
+                        self.reporter.failure(
+                            "unmatched",
+                            start_frame.elapsed(),
+                            "recv_error",
+                            format!("no rule matches {:?}", frame).as_str(),
+                        );
+                        if self.fail_fast() {
+                            return Ok(RulesOutcome::Closed);
+                        }
+                    }
+                    default_rule
+                }
+            };
+
+            if let Some(rule) = rule {
+                self.reply(mapping, rule).await?;
+
+                if let Some(target) = &rule.goto {
+                    return Ok(RulesOutcome::Goto(target));
+                }
+            }
+        }
+    }
+
+    /// Time from the last message sent to the arrival of the one just matched,
+    /// if any was sent since the previous one matched.
+    fn latency(&mut self) -> Option<Duration> {
+        let sent = self.last_send.take()?;
+        let received = self.conn.matched_at()?;
+
+        Some(received.saturating_duration_since(sent))
+    }
+
+    /// Sends the replies of a stub rule.
+    async fn reply(&mut self, mapping: &MappingState, rule: &Rule) -> Result<(), MessageError> {
+        if rule.delay_ms != 0 {
+            self.conn.wait(Duration::from_millis(rule.delay_ms)).await;
+        }
+
+        for name in &rule.reply {
+            self.conn
+                .send(name, &mapping.name_to_message[name], &[], Duration::ZERO)
+                .await?;
+            self.last_send = Some(Instant::now());
         }
-        reporter.report(self.report_path.as_str());
 
         Ok(())
     }
@@ -184,7 +1384,13 @@ impl fmt::Display for MessageError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             MessageError::Incomplete => "incomplete message in stream".fmt(fmt),
-            MessageError::NotEqual => "messages do not match".fmt(fmt),
+            MessageError::NotEqual { expected, received } => write!(
+                fmt,
+                "messages do not match at offset {}, expected {} bytes, received {} bytes",
+                first_mismatch(expected, received).unwrap_or_default(),
+                expected.len(),
+                received.len()
+            ),
             MessageError::BufferError => "error in reading or writing to buffer".fmt(fmt),
             MessageError::Other(err) => err.fmt(fmt),
         }
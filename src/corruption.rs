@@ -0,0 +1,97 @@
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use tracing::info;
+
+/// Controlled corruption applied to a sent message
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Corruption {
+    /// Number of random bits to be flipped
+    #[serde(default)]
+    pub flip_bits: usize,
+
+    /// Length, in bytes, the message is truncated to
+    #[serde(default)]
+    pub truncate: Option<usize>,
+
+    /// Sends the message twice
+    #[serde(default)]
+    pub duplicate: bool,
+
+    /// Sends the message only after the next `Send` action
+    #[serde(default)]
+    pub swap_next: bool,
+
+    /// Optional seed for the bit flips, so runs can be reproduced
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl Corruption {
+    /// Returns a corrupted copy of `message`.
+    ///
+    /// Truncation is applied first, then bit flips and, at last, duplication.
+    pub(crate) fn apply(&self, message: &Bytes) -> Bytes {
+        let mut corrupted = message.to_vec();
+
+        if let Some(len) = self.truncate {
+            corrupted.truncate(len);
+        }
+
+        if self.flip_bits > 0 && !corrupted.is_empty() {
+            let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+            info!("flipping {} bits with seed {}", self.flip_bits, seed);
+
+            let mut rng = StdRng::seed_from_u64(seed);
+            let total_bits = corrupted.len() * 8;
+            for bit in index::sample(&mut rng, total_bits, self.flip_bits.min(total_bits)) {
+                corrupted[bit / 8] ^= 1 << (bit % 8);
+            }
+        }
+
+        if self.duplicate {
+            corrupted.extend_from_within(..);
+        }
+
+        Bytes::from(corrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flipped_bits(a: &[u8], b: &[u8]) -> u32 {
+        a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+    }
+
+    #[test]
+    fn test_bit_flips_are_reproducible_with_seed() {
+        let message = Bytes::from_static(b"\x00\x00\x00\x00\x00\x00\x00\x00");
+        let corruption = Corruption {
+            flip_bits: 5,
+            seed: Some(42),
+            ..Default::default()
+        };
+
+        let first = corruption.apply(&message);
+        let second = corruption.apply(&message);
+
+        assert_eq!(first, second);
+        assert_eq!(flipped_bits(&message, &first), 5);
+    }
+
+    #[test]
+    fn test_truncate_and_duplicate_message() {
+        let message = Bytes::from_static(b"\x01\x02\x03\x04");
+        let corruption = Corruption {
+            truncate: Some(2),
+            duplicate: true,
+            ..Default::default()
+        };
+
+        assert_eq!(&corruption.apply(&message)[..], b"\x01\x02\x01\x02");
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt::Write;
+
+/// Bytes shown per row of a hexdump
+const ROW_LEN: usize = 8;
+
+/// Width of a row, as hex and ASCII columns
+const ROW_WIDTH: usize = ROW_LEN * 3 - 1 + ROW_LEN + 3;
+
+/// Offset of the first byte where `expected` and `received` differ, if any.
+pub(crate) fn first_mismatch(expected: &[u8], received: &[u8]) -> Option<usize> {
+    expected
+        .iter()
+        .zip(received)
+        .position(|(expected, received)| expected != received)
+        .or_else(|| {
+            (expected.len() != received.len()).then_some(expected.len().min(received.len()))
+        })
+}
+
+/// Aligned hexdump of `expected` and `received` side by side, marking the rows
+/// that differ, after the offset of the first mismatch and both lengths.
+pub(crate) fn hex_diff(expected: &[u8], received: &[u8]) -> String {
+    let mut diff = match first_mismatch(expected, received) {
+        Some(offset) => format!("first mismatch at offset {} ({:#x})\n", offset, offset),
+        None => "no mismatch\n".to_string(),
+    };
+    let _ = writeln!(
+        diff,
+        "expected {} bytes, received {} bytes\n",
+        expected.len(),
+        received.len()
+    );
+    let _ = writeln!(
+        diff,
+        "{:<8}  {:<width$}  received",
+        "offset",
+        "expected",
+        width = ROW_WIDTH
+    );
+
+    let len = expected.len().max(received.len());
+    for start in (0..len).step_by(ROW_LEN) {
+        let expected = row(expected, start);
+        let received = row(received, start);
+        let marker = if expected != received { "  <" } else { "" };
+
+        let _ = writeln!(
+            diff,
+            "{:08x}  {}  {}{}",
+            start,
+            hex_row(expected),
+            hex_row(received),
+            marker
+        );
+    }
+
+    diff
+}
+
+/// Hexdump of `data`, a row of offset, hex and ASCII columns per line.
+pub(crate) fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for start in (0..data.len()).step_by(ROW_LEN) {
+        let _ = writeln!(dump, "{:08x}  {}", start, hex_row(row(data, start)));
+    }
+
+    dump
+}
+
+/// Parses bytes written in hex, like `48 65 6c 6c 6f`, ignoring whitespace.
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let nibble = |digit: u8| (digit as char).to_digit(16);
+    digits
+        .chunks(2)
+        .map(|pair| Some((nibble(pair[0])? << 4 | nibble(pair[1])?) as u8))
+        .collect()
+}
+
+/// Bytes of the row starting at `start`, empty past the end.
+fn row(bytes: &[u8], start: usize) -> &[u8] {
+    &bytes[start.min(bytes.len())..(start + ROW_LEN).min(bytes.len())]
+}
+
+/// Hex and ASCII columns of a row, padded to the full row width.
+fn hex_row(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let ascii: String = bytes
+        .iter()
+        .map(|&byte| match byte {
+            b' ' => ' ',
+            byte if byte.is_ascii_graphic() => byte as char,
+            _ => '.',
+        })
+        .collect();
+
+    format!(
+        "{:<hex_width$} |{:<ROW_LEN$}|",
+        hex.join(" "),
+        ascii,
+        hex_width = ROW_LEN * 3 - 1
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_hex_ignores_whitespace() {
+        assert_eq!(from_hex("48 65 6c6C 6f\n"), Some(b"Hello".to_vec()));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("486"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("+f"), None);
+    }
+
+    #[test]
+    fn test_hex_diff_marks_first_mismatch_and_lengths() {
+        assert_eq!(first_mismatch(b"Hello", b"Hello"), None);
+        assert_eq!(first_mismatch(b"Hello", b"Help"), Some(3));
+        assert_eq!(first_mismatch(b"Hello", b"Hell"), Some(4));
+
+        let diff = hex_diff(b"Hello, world", b"Hello, World!");
+        let lines: Vec<&str> = diff.lines().collect();
+
+        assert_eq!(lines[0], "first mismatch at offset 7 (0x7)");
+        assert_eq!(lines[1], "expected 12 bytes, received 13 bytes");
+        assert_eq!(
+            lines[4],
+            "00000000  48 65 6c 6c 6f 2c 20 77 |Hello, w|  48 65 6c 6c 6f 2c 20 57 |Hello, W|  <"
+        );
+        assert_eq!(
+            lines[5],
+            "00000008  6f 72 6c 64             |orld    |  6f 72 6c 64 21          |orld!   |  <"
+        );
+    }
+}
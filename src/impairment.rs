@@ -0,0 +1,137 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::time::{Duration, Instant};
+
+/// Network impairments applied to all data sent in a connection
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Impairment {
+    /// Fixed latency added before each write, in milliseconds
+    #[serde(default)]
+    pub latency_ms: u64,
+
+    /// Maximum random latency added on top of `latency_ms`, in milliseconds
+    #[serde(default)]
+    pub jitter_ms: u64,
+
+    /// Maximum outbound rate, in bytes per second
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+
+    /// Interval between stalls, in milliseconds
+    #[serde(default)]
+    pub stall_every_ms: Option<u64>,
+
+    /// Duration of each stall, in milliseconds
+    #[serde(default)]
+    pub stall_ms: u64,
+
+    /// Optional seed for the jitter, so runs can be reproduced
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Keeps the runtime state of an `Impairment` for a single connection
+#[derive(Debug)]
+pub(crate) struct Impairer {
+    config: Impairment,
+    rng: StdRng,
+    next_stall: Option<Instant>,
+}
+
+/// Bandwidth is enforced by writing slices of data in fixed ticks
+const BANDWIDTH_TICKS_PER_SEC: u64 = 10;
+
+impl Impairer {
+    pub(crate) fn new(config: Impairment) -> Impairer {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let next_stall = config
+            .stall_every_ms
+            .map(|every| Instant::now() + Duration::from_millis(every));
+
+        Impairer {
+            config,
+            rng,
+            next_stall,
+        }
+    }
+
+    /// Latency to be waited before the next write
+    pub(crate) fn latency(&mut self) -> Duration {
+        let jitter = match self.config.jitter_ms {
+            0 => 0,
+            max => self.rng.gen_range(0..=max),
+        };
+
+        Duration::from_millis(self.config.latency_ms + jitter)
+    }
+
+    /// Maximum number of bytes written at once, given a message of `len` bytes
+    pub(crate) fn slice_len(&self, len: usize) -> usize {
+        match self.config.bandwidth {
+            Some(bps) => ((bps / BANDWIDTH_TICKS_PER_SEC) as usize).max(1),
+            None => len.max(1),
+        }
+    }
+
+    /// Time that `len` bytes take to be transmitted under the bandwidth cap
+    pub(crate) fn transmit_time(&self, len: usize) -> Duration {
+        match self.config.bandwidth {
+            Some(bps) if bps > 0 => Duration::from_secs_f64(len as f64 / bps as f64),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Stall to be waited now, if the stall interval has elapsed
+    pub(crate) fn stall(&mut self) -> Option<Duration> {
+        let next_stall = self.next_stall?;
+        let now = Instant::now();
+        if now < next_stall {
+            return None;
+        }
+
+        let every = Duration::from_millis(self.config.stall_every_ms.unwrap_or_default());
+        self.next_stall = Some(now + every);
+
+        Some(Duration::from_millis(self.config.stall_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_is_within_jitter_range() {
+        let mut impairer = Impairer::new(Impairment {
+            latency_ms: 10,
+            jitter_ms: 5,
+            seed: Some(1),
+            ..Default::default()
+        });
+
+        for _ in 0..100 {
+            let latency = impairer.latency();
+            assert!(latency >= Duration::from_millis(10));
+            assert!(latency <= Duration::from_millis(15));
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_limits_slices_and_transmit_time() {
+        let impairer = Impairer::new(Impairment {
+            bandwidth: Some(100),
+            ..Default::default()
+        });
+
+        assert_eq!(impairer.slice_len(1000), 10);
+        assert_eq!(impairer.transmit_time(50), Duration::from_millis(500));
+
+        let unlimited = Impairer::new(Impairment::default());
+        assert_eq!(unlimited.slice_len(1000), 1000);
+        assert_eq!(unlimited.transmit_time(1000), Duration::ZERO);
+    }
+}
@@ -0,0 +1,107 @@
+use bytes::Bytes;
+use serde::{Serialize, Serializer};
+use std::sync::{Arc, Mutex};
+
+/// Journal of a connection, shared by its handler, reporter and registry entry
+pub(crate) type SharedJournal = Arc<Mutex<Journal>>;
+
+/// Frames received on a connection, in order, and whether they were expected,
+/// for verifying what a client actually sent.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+/// A frame received on a connection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalEntry {
+    /// Number of the step of the connection report that received it, from 1
+    pub step: usize,
+    /// Message expected, or of the rule matched
+    pub message: Option<String>,
+    #[serde(serialize_with = "as_hex")]
+    pub data: Bytes,
+    pub status: FrameStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameStatus {
+    /// The frame was the expected message
+    Matched,
+    /// The frame was not the expected message
+    Mismatched,
+    /// No rule matched the frame
+    Unmatched,
+}
+
+impl Journal {
+    pub(crate) fn record(&mut self, entry: JournalEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Times the message `name` was received as expected.
+    pub fn received(&self, name: &str) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                entry.status == FrameStatus::Matched && entry.message.as_deref() == Some(name)
+            })
+            .count()
+    }
+
+    /// Frame received at the given step, if any.
+    pub fn at_step(&self, step: usize) -> Option<&JournalEntry> {
+        self.entries.iter().find(|entry| entry.step == step)
+    }
+
+    /// Frames that were mismatched or unmatched.
+    pub fn unexpected(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status != FrameStatus::Matched)
+    }
+}
+
+fn as_hex<S: Serializer>(data: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+    serializer.serialize_str(&hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_answers_verification_queries() {
+        let mut journal = Journal::default();
+        let entries = [
+            (1, Some("heartbeat"), "\x01", FrameStatus::Matched),
+            (2, Some("logon"), "\x03", FrameStatus::Mismatched),
+            (3, Some("heartbeat"), "\x01", FrameStatus::Matched),
+            (4, None, "\x7f", FrameStatus::Unmatched),
+        ];
+        for (step, message, data, status) in entries {
+            journal.record(JournalEntry {
+                step,
+                message: message.map(String::from),
+                data: Bytes::from(data),
+                status,
+            });
+        }
+
+        assert_eq!(journal.received("heartbeat"), 2);
+        assert_eq!(journal.received("logon"), 0);
+        assert_eq!(journal.at_step(2).unwrap().data, Bytes::from("\x03"));
+        assert_eq!(journal.unexpected().count(), 2);
+        assert_eq!(
+            serde_json::to_value(&journal).unwrap()[3],
+            serde_json::json!({"step": 4, "message": null, "data": "7f", "status": "unmatched"})
+        );
+    }
+}
@@ -1,7 +1,16 @@
+pub mod capture;
 pub mod cli;
 pub mod connection;
+pub mod corruption;
+pub mod hexdump;
+pub mod impairment;
+pub mod journal;
 pub mod mapping;
+pub mod registry;
 pub mod reporter;
 pub mod server;
+pub mod shutdown;
+pub mod signals;
+pub mod transcript;
 
 pub type Result<T> = anyhow::Result<T, anyhow::Error>;
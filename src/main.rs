@@ -1,26 +1,59 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use env_logger::{Builder, Env};
-use log::info;
+use std::process::ExitCode;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::sync::Notify;
+use tokio::time::Duration;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
-use mocktide::cli::Cli;
+use mocktide::capture::Capture;
+use mocktide::cli::{Cli, LogFormat};
 use mocktide::server::{run_tcp_server, ServerConfig};
 
+/// Exit code of a server that could not start, apart from the outcomes of a run
+const NOT_STARTED: u8 = 4;
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Cli::parse();
+async fn main() -> ExitCode {
+    let args = match Cli::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            let _ = err.print();
+            // Help and version are printed on stdout, as requested
+            return match err.use_stderr() {
+                true => ExitCode::from(NOT_STARTED),
+                false => ExitCode::SUCCESS,
+            };
+        }
+    };
+
+    match run(args).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            ExitCode::from(NOT_STARTED)
+        }
+    }
+}
 
-    Builder::from_env(Env::default().default_filter_or(match args.verbose {
-        0 => "info",
-        1 => "debug",
-        2..=u8::MAX => "debug", // TODO: Tracing
-    }))
-    .try_init()
-    .with_context(|| "logger could not be initialized: {:#?}")?;
+/// Runs the server, failing only if it could not start.
+async fn run(args: Cli) -> Result<ExitCode> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(match args.verbose {
+            0 => "info",
+            1 => "debug",
+            2..=u8::MAX => "trace",
+        })
+    });
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match args.log_format {
+        LogFormat::Pretty => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().try_init(),
+    }
+    .map_err(|err| anyhow!("logger could not be initialized: {:}", err))?;
 
     if !args.mapping_file.exists() {
         return Err(anyhow!("file {:#?} does not exist", args.mapping_file));
@@ -31,22 +64,69 @@ async fn main() -> Result<()> {
         .await
         .with_context(|| format!("error binding to {}", &address))?;
 
-    info!("server will start in address {}", &address);
+    info!(
+        "server will start in address {}",
+        listener
+            .local_addr()
+            .map_or(address, |address| address.to_string())
+    );
 
-    let notify = Arc::new(Notify::new());
-    let notify_here = notify.clone();
+    let capture = match &args.capture {
+        Some(path) => Some(Arc::new(
+            Capture::create(path).with_context(|| format!("error creating {:?}", path))?,
+        )),
+        None => None,
+    };
+
+    if let Some(dir) = &args.transcript_dir {
+        std::fs::create_dir_all(dir).with_context(|| format!("error creating {:?}", dir))?;
+    }
+
+    let admin_listener = match args.admin_port {
+        Some(port) => {
+            let address = format!("{}:{}", &args.host, port);
+            Some(
+                TcpListener::bind(&address)
+                    .await
+                    .with_context(|| format!("error binding admin API to {}", &address))?,
+            )
+        }
+        None => None,
+    };
 
     let config = ServerConfig {
         mapping_file_path: args.mapping_file.to_string_lossy().to_string(),
         report_path: args.report.to_string_lossy().to_string(),
-        shutdown_notify: notify,
+        report_formats: args.report_format,
+        shutdown_notify: Arc::new(Notify::new()),
+        grace_period: Duration::from_millis(args.grace_period_ms),
+        fail_fast: args.fail_fast,
+        capture,
+        transcript_dir: args
+            .transcript_dir
+            .map(|dir| dir.to_string_lossy().to_string()),
+        watch_mapping: args.watch,
+        interactive: args.interactive,
+        admin_listener,
+        ready_file: args
+            .ready_file
+            .map(|path| path.to_string_lossy().to_string()),
+        ready_line: args.ready_line,
     };
 
-    tokio::select! {
-        _ = run_tcp_server(listener, config) => {}
-        _ = signal::ctrl_c() => { info!("server interrupted") }
-        _ = notify_here.notified() => { info!("server shutdown called") }
+    let report = run_tcp_server(listener, config, signal::ctrl_c()).await?;
+    let summary = &report.summary;
+    for err in &report.report_errors {
+        error!("report not written, {}", err);
+    }
+    let outcome = report.outcome();
+    info!(
+        "{:?}: {} connections, {} tests, {} failures, {} errors",
+        outcome, summary.connections, summary.tests, summary.failures, summary.errors
+    );
+    if let Some(stats) = summary.latency.stats() {
+        info!("client latency: {}", stats);
     }
 
-    Ok(())
+    Ok(ExitCode::from(outcome as u8))
 }
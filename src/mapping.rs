@@ -1,37 +1,173 @@
-use anyhow::Context;
-use bytes::Bytes;
-use log::debug;
+use anyhow::{anyhow, Context};
+use bytes::{Bytes, BytesMut};
 use serde::{de, Deserialize, Deserializer};
 use std::{
     collections::{HashMap, VecDeque},
     fs,
-    sync::Arc,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
+use tracing::debug;
 
 use tokio::sync::RwLock;
 
+use crate::corruption::Corruption;
+use crate::impairment::Impairment;
+use crate::signals::Signals;
+
 #[derive(Debug)]
 pub(crate) struct MappingGuard {
-    mapping: Mapping,
+    mapping: Mutex<Mapping>,
+    /// Set on the listening socket once, so no reload can change it
+    receive_buffer: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Mapping {
     pub state: Arc<RwLock<MappingState>>,
+    pub signals: Arc<Signals>,
 }
 
 #[derive(Debug)]
 pub(crate) struct MappingState {
     pub mapping_name: String,
     pub name_to_message: HashMap<String, Bytes>,
-    pub message_actions: VecDeque<MessageAction>,
+    pub scenario: Scenario,
+    pub scenarios: HashMap<String, Scenario>,
+    pub select: Vec<Selector>,
+    pub impairment: Option<Impairment>,
+    pub receive_buffer: Option<usize>,
 }
 
 #[derive(Debug)]
 pub struct MappingFile {
     name: String,
     messages: HashMap<String, Bytes>,
-    actions: VecDeque<MessageAction>,
+    scenario: Scenario,
+    scenarios: HashMap<String, Scenario>,
+    select: Vec<Selector>,
+    impairment: Option<Impairment>,
+    receive_buffer: Option<usize>,
+}
+
+/// Defines how the server handles a connection
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct Scenario {
+    /// How the connection is handled. Defaults to `script`.
+    #[serde(default)]
+    pub mode: Mode,
+
+    /// Actions executed, in order, in `script` mode
+    #[serde(default, rename = "actions")]
+    pub message_actions: VecDeque<MessageAction>,
+
+    /// Rules followed in `stub` mode
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+
+    /// Optional reply for anything no rule matches, in `stub` mode
+    #[serde(default, rename = "default")]
+    pub default_rule: Option<Rule>,
+
+    /// State the connection starts in, in `states` mode
+    #[serde(default, rename = "initial")]
+    pub initial_state: String,
+
+    /// Named states, in `states` mode
+    #[serde(default)]
+    pub states: HashMap<String, State>,
+
+    /// Optional group joined by the connections in this scenario, for broadcasts
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+/// Selects the scenario of a connection. All the criteria set must match.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Selector {
+    /// Scenario used by the connections that match
+    pub scenario: String,
+
+    /// Accept order of the connection, starting from 1
+    #[serde(default)]
+    pub connection: Option<usize>,
+
+    /// Peer IP address
+    #[serde(default)]
+    pub peer_ip: Option<IpAddr>,
+
+    /// Peer port
+    #[serde(default)]
+    pub peer_port: Option<u16>,
+
+    /// First message received in the connection, which is kept to be handled
+    /// by the selected scenario
+    #[serde(default)]
+    pub first_message: Option<String>,
+
+    /// Time to wait for the first message, after which the next selector is
+    /// checked, without limit by default
+    #[serde(default)]
+    pub first_message_timeout_ms: Option<u64>,
+}
+
+/// Defines how a scenario handles a connection
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Mode {
+    /// Executes `actions` in order
+    #[default]
+    Script,
+    /// Replies to received messages following `rules`
+    Stub,
+    /// Moves between named `states`, starting from the `initial` one
+    States,
+}
+
+/// Stub rule replying to a received message
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Rule {
+    /// Message that triggers the rule. Unused by the default rule.
+    #[serde(default)]
+    pub when: String,
+
+    /// Messages to be replied, in order
+    #[serde(default)]
+    pub reply: Vec<String>,
+
+    /// Optional delay before replying, in milliseconds. Defaults to zero.
+    #[serde(default)]
+    pub delay_ms: u64,
+
+    /// Optional state moved to after replying, in `states` mode
+    #[serde(default)]
+    pub goto: Option<String>,
+}
+
+/// Named state of a `states` mode mapping
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct State {
+    /// Actions executed, in order, when entering the state
+    #[serde(default)]
+    pub actions: VecDeque<MessageAction>,
+
+    /// Rules followed after the actions, until one of them moves to another state
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+
+    /// Optional reply for anything no rule matches
+    #[serde(default)]
+    pub default: Option<Rule>,
+
+    /// Optional state moved to once the actions are done, or, for states
+    /// with rules, once `timeout_ms` elapses
+    #[serde(default)]
+    pub next: Option<String>,
+
+    /// Optional time the rules are followed for, in milliseconds
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -46,6 +182,42 @@ pub(crate) struct MessageAction {
     /// Optional waiting time, in seconds. Defaults to zero.
     #[serde(default)]
     pub wait_for: u64,
+
+    /// Optional size of the chunks a sent message is split into, in bytes.
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+
+    /// Optional byte offsets where a sent message is split.
+    #[serde(default)]
+    pub split_at: Vec<usize>,
+
+    /// Optional delay between sent chunks, in milliseconds. Defaults to zero.
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+
+    /// Optional messages to be written right after `message`, in the same write.
+    #[serde(default)]
+    pub coalesce: Vec<String>,
+
+    /// Optional corruption applied to the sent message
+    #[serde(default)]
+    pub corrupt: Option<Corruption>,
+
+    /// Duration of time-bounded actions, in milliseconds. Defaults to zero.
+    #[serde(default)]
+    pub duration_ms: u64,
+
+    /// Name of the signal or barrier shared between connections
+    #[serde(default)]
+    pub signal: String,
+
+    /// Number of connections a barrier waits for. Defaults to two.
+    #[serde(default)]
+    pub parties: Option<usize>,
+
+    /// Optional group of connections a message is broadcast to. Defaults to all.
+    #[serde(default)]
+    pub group: Option<String>,
 }
 
 /// Defines actions the server can perform
@@ -57,37 +229,167 @@ pub(crate) enum Action {
     Recv,
     /// Shutdown the server, closing all connections
     Shutdown,
+    /// Expect the peer to send nothing during `duration_ms`
+    ExpectSilence,
+    /// Expect the peer to close the connection within `duration_ms`
+    ExpectClose,
+    /// Stop reading from the connection during `duration_ms`, or without it
+    /// until the next action reading from it
+    PauseRead,
+    /// Send a mapped message to all live connections, or the ones in `group`
+    Broadcast,
+    /// Raise a `signal` to other connections
+    Signal,
+    /// Wait for a `signal` from another connection, for at most `duration_ms`
+    WaitFor,
+    /// Wait for `parties` connections to reach the `signal` barrier, for at most `duration_ms`
+    Barrier,
     /// Placeholder
     Unknown,
 }
 
-impl MappingGuard {
-    pub(crate) fn new(config: String) -> MappingGuard {
-        MappingGuard {
-            mapping: Mapping::new(config),
+impl Action {
+    /// Whether the action operates on a mapped message.
+    pub(crate) fn requires_message(&self) -> bool {
+        matches!(self, Action::Send | Action::Recv | Action::Broadcast)
+    }
+
+    /// Whether the action operates on a shared signal.
+    pub(crate) fn requires_signal(&self) -> bool {
+        matches!(self, Action::Signal | Action::WaitFor | Action::Barrier)
+    }
+
+    /// Whether the action lasts for `duration_ms`, which must be set.
+    pub(crate) fn requires_duration(&self) -> bool {
+        matches!(self, Action::ExpectSilence | Action::ExpectClose)
+    }
+}
+
+impl MessageAction {
+    /// Name of the action in logs and reports: its message, if any, or its kind.
+    pub(crate) fn name(&self) -> String {
+        if !self.message.is_empty() {
+            self.message.clone()
+        } else if !self.signal.is_empty() {
+            format!("{:?} {}", self.execute, self.signal)
+        } else {
+            format!("{:?}", self.execute)
+        }
+    }
+
+    /// Whether the action waits for the peer, another connection or some time.
+    pub(crate) fn waits(&self) -> bool {
+        self.wait_for != 0
+            || match self.execute {
+                Action::Recv
+                | Action::ExpectSilence
+                | Action::ExpectClose
+                | Action::WaitFor
+                | Action::Barrier => true,
+                Action::PauseRead => self.duration_ms != 0,
+                _ => false,
+            }
+    }
+
+    /// Timeout of time-bounded actions, where zero means waiting forever.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self.duration_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Whether the message must be sent only after the next `Send` action.
+    pub(crate) fn is_swapped(&self) -> bool {
+        self.corrupt.as_ref().is_some_and(|c| c.swap_next)
+    }
+
+    /// Offsets where a message of `len` bytes must be split before being sent.
+    pub(crate) fn split_points(&self, len: usize) -> Vec<usize> {
+        match self.chunk_size {
+            Some(size) => (size..len).step_by(size).collect(),
+            None => self
+                .split_at
+                .iter()
+                .copied()
+                .filter(|&offset| offset > 0 && offset < len)
+                .collect(),
         }
     }
+}
+
+impl Selector {
+    /// Whether the connection of given accept order and peer address matches
+    /// the criteria set, except for the first message.
+    pub(crate) fn matches_peer(&self, index: usize, peer: Option<SocketAddr>) -> bool {
+        self.connection.is_none_or(|connection| connection == index)
+            && self
+                .peer_ip
+                .is_none_or(|ip| peer.is_some_and(|peer| peer.ip() == ip))
+            && self
+                .peer_port
+                .is_none_or(|port| peer.is_some_and(|peer| peer.port() == port))
+    }
+}
+
+impl MappingGuard {
+    pub(crate) fn new(config: String) -> crate::Result<MappingGuard> {
+        let mapping = Mapping::new(config)?;
+        let receive_buffer = mapping.state.try_read().unwrap().receive_buffer;
+
+        Ok(MappingGuard {
+            mapping: Mutex::new(mapping),
+            receive_buffer,
+        })
+    }
 
     /// Gets underlying mapping, increasing its reference count.
     pub(crate) fn mapping(&self) -> Mapping {
-        self.mapping.clone()
+        self.mapping.lock().unwrap().clone()
+    }
+
+    /// Replaces the mapping of new connections, live ones keep the one they
+    /// started with. Signals stay shared between both.
+    ///
+    /// Fails if the new mapping changes the receive buffer, which only takes
+    /// effect on restart.
+    pub(crate) fn replace(&self, state: MappingState) -> crate::Result<()> {
+        if state.receive_buffer != self.receive_buffer {
+            return Err(anyhow!(
+                "receive_buffer can not change from {:?} to {:?} without restarting the server",
+                self.receive_buffer,
+                state.receive_buffer
+            ));
+        }
+
+        self.mapping.lock().unwrap().state = Arc::new(RwLock::new(state));
+        Ok(())
     }
 }
 
 impl Mapping {
-    pub(crate) fn new(config: String) -> Mapping {
-        let state = Arc::new(RwLock::new(MappingState::from_file(config).unwrap()));
+    pub(crate) fn new(config: String) -> crate::Result<Mapping> {
+        let state = Arc::new(RwLock::new(MappingState::from_file(config)?));
 
-        Mapping { state }
+        Ok(Mapping {
+            state,
+            signals: Arc::new(Signals::default()),
+        })
     }
 }
 
 impl MappingState {
     pub fn from_file(config_path: String) -> crate::Result<MappingState> {
-        let file_content = fs::read_to_string(config_path).unwrap();
+        let file_content = fs::read_to_string(&config_path)
+            .with_context(|| format!("error reading {}", config_path))?;
+
+        MappingState::from_yaml(&file_content)
+    }
 
-        let parsed: MappingFile = serde_yaml::from_str(file_content.as_str())
-            .with_context(|| "error parsing the mapping file")?;
+    /// Parses the content of a mapping file.
+    pub fn from_yaml(content: &str) -> crate::Result<MappingState> {
+        let parsed: MappingFile =
+            serde_yaml::from_str(content).with_context(|| "error parsing the mapping file")?;
 
         let mut name_to_message: HashMap<String, Bytes> = HashMap::new();
 
@@ -100,9 +402,27 @@ impl MappingState {
         Ok(MappingState {
             mapping_name: parsed.name,
             name_to_message,
-            message_actions: parsed.actions,
+            scenario: parsed.scenario,
+            scenarios: parsed.scenarios,
+            select: parsed.select,
+            impairment: parsed.impairment,
+            receive_buffer: parsed.receive_buffer,
         })
     }
+
+    /// Message to be sent by `action`: its mapped message followed by the
+    /// coalesced ones, if any, with the configured corruption applied.
+    pub(crate) fn outgoing_message(&self, action: &MessageAction) -> Bytes {
+        let mut buffer = BytesMut::from(&self.name_to_message[&action.message][..]);
+        for name in &action.coalesce {
+            buffer.extend_from_slice(&self.name_to_message[name]);
+        }
+
+        match &action.corrupt {
+            Some(corruption) => corruption.apply(&buffer.freeze()),
+            None => buffer.freeze(),
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for MappingFile {
@@ -114,18 +434,29 @@ impl<'de> Deserialize<'de> for MappingFile {
         struct Helper {
             name: String,
             messages: HashMap<String, String>,
-            actions: VecDeque<MessageAction>,
+            #[serde(flatten)]
+            scenario: Scenario,
+            #[serde(default)]
+            scenarios: HashMap<String, Scenario>,
+            #[serde(default)]
+            select: Vec<Selector>,
+            #[serde(default)]
+            impairment: Option<Impairment>,
+            #[serde(default)]
+            receive_buffer: Option<usize>,
         }
 
         let helper = Helper::deserialize(deserializer)?;
 
-        for action in &helper.actions {
-            if action.message.is_empty() && action.execute != Action::Shutdown {
-                return Err(de::Error::custom(format!(
-                    "Action {:?} requires a mapped message",
-                    action.execute
-                )));
-            }
+        validate_scenario(&helper.scenario, &helper.messages).map_err(de::Error::custom)?;
+        for (name, scenario) in &helper.scenarios {
+            validate_scenario(scenario, &helper.messages)
+                .map_err(|err| de::Error::custom(format!("Scenario '{}': {}", name, err)))?;
+        }
+        validate_selectors(&helper.select, &helper.scenarios, &helper.messages)
+            .map_err(de::Error::custom)?;
+        if let Some(impairment) = &helper.impairment {
+            validate_impairment(impairment).map_err(de::Error::custom)?;
         }
 
         let mut messages = HashMap::new();
@@ -133,17 +464,266 @@ impl<'de> Deserialize<'de> for MappingFile {
             messages.insert(k, Bytes::from(v));
         }
         // To not complicate even more the flow of ConnHandler,
-        // a null byte mapping for shutdown action
+        // a null byte mapping for actions without a message
         messages.insert("".to_string(), Bytes::from("\x00"));
 
         Ok(MappingFile {
             name: helper.name,
             messages,
-            actions: helper.actions,
+            scenario: helper.scenario,
+            scenarios: helper.scenarios,
+            select: helper.select,
+            impairment: helper.impairment,
+            receive_buffer: helper.receive_buffer,
         })
     }
 }
 
+/// Checks that the actions, rules and states of `scenario` are valid for its mode.
+fn validate_scenario(
+    scenario: &Scenario,
+    messages: &HashMap<String, String>,
+) -> Result<(), String> {
+    validate_actions(&scenario.message_actions, messages)?;
+    validate_rules(&scenario.rules, messages)?;
+    if let Some(default) = &scenario.default_rule {
+        validate_replies(default, messages)?;
+    }
+    if scenario
+        .rules
+        .iter()
+        .chain(&scenario.default_rule)
+        .any(|rule| rule.goto.is_some())
+    {
+        return Err("Rules can only use goto in states mode".to_string());
+    }
+    if scenario.mode == Mode::States {
+        validate_states(&scenario.initial_state, &scenario.states, messages)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that `selectors` set some criteria and refer to existing scenarios.
+fn validate_selectors(
+    selectors: &[Selector],
+    scenarios: &HashMap<String, Scenario>,
+    messages: &HashMap<String, String>,
+) -> Result<(), String> {
+    for selector in selectors {
+        if !scenarios.contains_key(&selector.scenario) {
+            return Err(format!("Scenario '{}' is not defined", selector.scenario));
+        }
+
+        if selector.connection.is_none()
+            && selector.peer_ip.is_none()
+            && selector.peer_port.is_none()
+            && selector.first_message.is_none()
+        {
+            return Err(format!(
+                "Selector for scenario '{}' requires at least one criterion",
+                selector.scenario
+            ));
+        }
+
+        if let Some(name) = &selector.first_message {
+            match messages.get(name) {
+                Some(value) if !value.is_empty() => {}
+                _ => return Err(format!("First message '{}' is not mapped", name)),
+            }
+        }
+
+        if selector.first_message_timeout_ms.is_some() && selector.first_message.is_none() {
+            return Err(format!(
+                "Selector for scenario '{}' sets first_message_timeout_ms without first_message",
+                selector.scenario
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `actions` are consistent and only refer to mapped messages.
+fn validate_actions(
+    actions: &VecDeque<MessageAction>,
+    messages: &HashMap<String, String>,
+) -> Result<(), String> {
+    // Message of the Send waiting for the next one, swapped with it
+    let mut swapped: Option<&String> = None;
+
+    for action in actions {
+        if action.message.is_empty() && action.execute.requires_message() {
+            return Err(format!(
+                "Action {:?} requires a mapped message",
+                action.execute
+            ));
+        }
+
+        if action.signal.is_empty() && action.execute.requires_signal() {
+            return Err(format!("Action {:?} requires a signal", action.execute));
+        }
+
+        if action.parties == Some(0) {
+            return Err(format!(
+                "Barrier '{}' requires at least one party",
+                action.signal
+            ));
+        }
+
+        if action.duration_ms == 0 && action.execute.requires_duration() {
+            return Err(format!(
+                "Action {:?} requires a duration_ms greater than zero",
+                action.execute
+            ));
+        }
+
+        if !action.message.is_empty() && !messages.contains_key(&action.message) {
+            return Err(format!("Message '{}' is not mapped", action.message));
+        }
+
+        if action.chunk_size.is_some() && !action.split_at.is_empty() {
+            return Err(format!(
+                "Action for '{}' can not set both chunk_size and split_at",
+                action.message
+            ));
+        }
+
+        if action.chunk_size == Some(0) {
+            return Err(format!(
+                "Action for '{}' requires a chunk_size greater than zero",
+                action.message
+            ));
+        }
+
+        if action.split_at.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!(
+                "Action for '{}' requires split_at offsets in increasing order",
+                action.message
+            ));
+        }
+
+        for name in &action.coalesce {
+            if !messages.contains_key(name) {
+                return Err(format!("Message '{}' to be coalesced is not mapped", name));
+            }
+        }
+
+        if action.execute == Action::Send {
+            match (swapped, action.is_swapped()) {
+                (Some(previous), true) => {
+                    return Err(format!(
+                    "Action for '{}' can not swap_next, '{}' is already waiting for the next Send",
+                    action.message, previous
+                ))
+                }
+                (_, true) => swapped = Some(&action.message),
+                (_, false) => swapped = None,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that stub `rules` are triggered by, and reply with, mapped messages.
+fn validate_rules(rules: &[Rule], messages: &HashMap<String, String>) -> Result<(), String> {
+    for rule in rules {
+        match messages.get(&rule.when) {
+            Some(value) if !value.is_empty() => {}
+            _ => {
+                return Err(format!(
+                    "Rule requires a mapped, non-empty message in 'when', got '{}'",
+                    rule.when
+                ))
+            }
+        }
+
+        validate_replies(rule, messages)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that `states` are valid and only move to existing states.
+fn validate_states(
+    initial: &str,
+    states: &HashMap<String, State>,
+    messages: &HashMap<String, String>,
+) -> Result<(), String> {
+    if !states.contains_key(initial) {
+        return Err(format!("Initial state '{}' is not defined", initial));
+    }
+
+    for (name, state) in states {
+        validate_actions(&state.actions, messages)?;
+        validate_rules(&state.rules, messages)?;
+        if let Some(default) = &state.default {
+            validate_replies(default, messages)?;
+        }
+
+        let targets = state
+            .rules
+            .iter()
+            .chain(&state.default)
+            .filter_map(|rule| rule.goto.as_ref())
+            .chain(&state.next);
+        for target in targets {
+            if !states.contains_key(target) {
+                return Err(format!(
+                    "State '{}' moves to undefined state '{}'",
+                    name, target
+                ));
+            }
+        }
+    }
+
+    // States without rules move to the next one at once, so a loop of them
+    // must wait for something at some point
+    for start in states.keys() {
+        let mut looped = vec![];
+        let mut current = start;
+        loop {
+            let state = &states[current];
+            if !state.rules.is_empty() || state.actions.iter().any(MessageAction::waits) {
+                break;
+            }
+            if looped.contains(&current) {
+                return Err(format!(
+                    "States {:?} loop forever without waiting for anything",
+                    looped
+                ));
+            }
+            looped.push(current);
+
+            match &state.next {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_replies(rule: &Rule, messages: &HashMap<String, String>) -> Result<(), String> {
+    for name in &rule.reply {
+        if !messages.contains_key(name) {
+            return Err(format!("Message '{}' to be replied is not mapped", name));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_impairment(impairment: &Impairment) -> Result<(), String> {
+    if impairment.bandwidth == Some(0) {
+        return Err("Impairment requires a bandwidth greater than zero".to_string());
+    }
+
+    Ok(())
+}
+
 impl PartialEq for Action {
     fn eq(&self, other: &Self) -> bool {
         matches!(
@@ -151,6 +731,13 @@ impl PartialEq for Action {
             (Action::Send, Action::Send)
                 | (Action::Recv, Action::Recv)
                 | (Action::Shutdown, Action::Shutdown)
+                | (Action::ExpectSilence, Action::ExpectSilence)
+                | (Action::ExpectClose, Action::ExpectClose)
+                | (Action::PauseRead, Action::PauseRead)
+                | (Action::Broadcast, Action::Broadcast)
+                | (Action::Signal, Action::Signal)
+                | (Action::WaitFor, Action::WaitFor)
+                | (Action::Barrier, Action::Barrier)
                 | (Action::Unknown, Action::Unknown),
         )
     }
@@ -195,6 +782,111 @@ mod tests {
             - execute: Recv
     "#;
 
+    static MAPPING_FRAMING_YAML: &str = r#"
+        name: framing test
+
+        messages:
+            msg1: "\x01\x02\x03\x04\x05"
+            msg2: "\x06"
+
+        actions:
+            - message: msg1
+              execute: Send
+              chunk_size: 2
+              chunk_delay_ms: 10
+            - message: msg1
+              execute: Send
+              split_at: [1, 4]
+              coalesce: [msg2]
+    "#;
+
+    static MAPPING_PAUSE_YAML: &str = r#"
+        name: pause test
+
+        receive_buffer: 4096
+
+        messages:
+            msg1: "\x01\x02\x03"
+
+        actions:
+            - execute: PauseRead
+              duration_ms: 500
+            - message: msg1
+              execute: Recv
+    "#;
+
+    static MAPPING_STUB_YAML: &str = r#"
+        name: stub test
+        mode: stub
+
+        messages:
+            ping: "\x01"
+            pong: "\x02"
+            nack: "\xff"
+
+        rules:
+            - when: ping
+              reply: [pong, pong]
+              delay_ms: 10
+
+        default:
+            reply: [nack]
+    "#;
+
+    static MAPPING_STATES_YAML: &str = r#"
+        name: states test
+        mode: states
+        initial: logon
+
+        messages:
+            logon: "\x01"
+            ack: "\x02"
+            logout: "\x03"
+
+        states:
+            logon:
+                rules:
+                    - when: logon
+                      reply: [ack]
+                      goto: trading
+            trading:
+                rules:
+                    - when: logout
+                      goto: done
+                timeout_ms: 1000
+                next: done
+            done:
+                actions:
+                    - execute: ExpectClose
+                      duration_ms: 100
+    "#;
+
+    static MAPPING_SCENARIOS_YAML: &str = r#"
+        name: scenarios test
+
+        messages:
+            logon: "\x01"
+            ack: "\x02"
+
+        scenarios:
+            primary:
+                actions:
+                    - message: logon
+                      execute: Recv
+            drop_copy:
+                mode: stub
+                rules:
+                    - when: logon
+                      reply: [ack]
+
+        select:
+            - connection: 1
+              scenario: primary
+            - peer_ip: 127.0.0.1
+              first_message: logon
+              scenario: drop_copy
+    "#;
+
     #[test]
     fn test_yaml_file_is_correctly_deserialized() {
         let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(MAPPING_YAML);
@@ -209,4 +901,203 @@ mod tests {
 
         assert_err!(parsed);
     }
+
+    #[test]
+    fn test_framing_options_are_converted_to_split_points() {
+        let parsed: MappingFile = serde_yaml::from_str(MAPPING_FRAMING_YAML).unwrap();
+
+        let actions = parsed.scenario.message_actions;
+        assert_eq!(actions[0].split_points(5), vec![2, 4]);
+        assert_eq!(actions[1].split_points(6), vec![1, 4]);
+        assert_eq!(actions[1].coalesce, vec!["msg2".to_string()]);
+    }
+
+    #[test]
+    fn test_pause_read_does_not_require_a_mapped_msg() {
+        let parsed: MappingFile = serde_yaml::from_str(MAPPING_PAUSE_YAML).unwrap();
+
+        assert_eq!(parsed.receive_buffer, Some(4096));
+        assert_eq!(
+            parsed.scenario.message_actions[0].execute,
+            Action::PauseRead
+        );
+        assert_eq!(parsed.scenario.message_actions[0].duration_ms, 500);
+
+        let mapping = MAPPING_PAUSE_YAML.replace("duration_ms: 500", "duration_ms: 0");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_ok!(parsed);
+    }
+
+    #[test]
+    fn test_stub_mapping_is_correctly_deserialized() {
+        let parsed: MappingFile = serde_yaml::from_str(MAPPING_STUB_YAML).unwrap();
+
+        let scenario = parsed.scenario;
+        assert_eq!(scenario.mode, Mode::Stub);
+        assert!(scenario.message_actions.is_empty());
+        assert_eq!(scenario.rules[0].reply, vec!["pong", "pong"]);
+        assert_eq!(scenario.default_rule.unwrap().reply, vec!["nack"]);
+    }
+
+    #[test]
+    fn test_stub_mapping_fails_to_deserialize_unmapped_reply() {
+        let mapping = MAPPING_STUB_YAML.replace("reply: [nack]", "reply: [ack]");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_states_mapping_is_correctly_deserialized() {
+        let parsed: MappingFile = serde_yaml::from_str(MAPPING_STATES_YAML).unwrap();
+
+        let scenario = parsed.scenario;
+        assert_eq!(scenario.mode, Mode::States);
+        assert_eq!(scenario.initial_state, "logon");
+        assert_eq!(scenario.states.len(), 3);
+        assert_eq!(scenario.states["trading"].next.as_deref(), Some("done"));
+    }
+
+    #[test]
+    fn test_states_mapping_fails_to_deserialize_expectation_without_duration() {
+        let mapping = MAPPING_STATES_YAML.replace("duration_ms: 100", "duration_ms: 0");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+
+        let mapping = MAPPING_STATES_YAML.replace("duration_ms: 100", "message: logout");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_states_mapping_fails_to_deserialize_undefined_state() {
+        let mapping = MAPPING_STATES_YAML.replace("goto: trading", "goto: trade");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+
+        let mapping = MAPPING_STATES_YAML.replace("initial: logon", "initial: login");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_states_mapping_fails_to_deserialize_loop_without_wait() {
+        let looping = MAPPING_STATES_YAML.replace(
+            "duration_ms: 100",
+            "duration_ms: 100\n                next: trading\n",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&looping);
+        assert_ok!(parsed);
+
+        let looping = looping.replace(
+            "execute: ExpectClose\n                      duration_ms: 100",
+            "execute: Send\n                      message: ack",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&looping);
+        assert_ok!(parsed);
+
+        let looping = looping.replace("next: trading", "next: done");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&looping);
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_stub_mapping_fails_to_deserialize_default_goto() {
+        let mapping =
+            MAPPING_STUB_YAML.replace("reply: [nack]", "reply: [nack]\n            goto: done");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_scenarios_mapping_is_correctly_deserialized() {
+        let parsed: MappingFile = serde_yaml::from_str(MAPPING_SCENARIOS_YAML).unwrap();
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        assert_eq!(parsed.scenarios.len(), 2);
+        assert!(parsed.scenario.message_actions.is_empty());
+        assert!(parsed.select[0].matches_peer(1, Some(peer)));
+        assert!(!parsed.select[0].matches_peer(2, Some(peer)));
+        assert!(parsed.select[1].matches_peer(2, Some(peer)));
+        assert!(!parsed.select[1].matches_peer(2, None));
+    }
+
+    #[test]
+    fn test_scenarios_mapping_fails_to_deserialize_undefined_scenario() {
+        let mapping = MAPPING_SCENARIOS_YAML.replace("scenario: primary", "scenario: main");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_scenarios_mapping_requires_first_message_for_its_timeout() {
+        let mapping = MAPPING_SCENARIOS_YAML.replace(
+            "scenario: primary",
+            "scenario: primary\n              first_message_timeout_ms: 100",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+
+        let mapping = MAPPING_SCENARIOS_YAML.replace(
+            "scenario: drop_copy\n",
+            "scenario: drop_copy\n              first_message_timeout_ms: 100\n",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_ok!(parsed);
+    }
+
+    #[test]
+    fn test_yaml_file_fails_to_deserialize_signal_action_without_signal() {
+        let mapping = MAPPING_YAML.replace("execute: Send", "execute: WaitFor");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+
+        let mapping = mapping.replace(
+            "execute: WaitFor",
+            "execute: WaitFor\n              signal: ready",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_ok!(parsed);
+    }
+
+    #[test]
+    fn test_yaml_file_fails_to_deserialize_conflicting_framing_options() {
+        let mapping = MAPPING_FRAMING_YAML.replace("split_at: [1, 4]", "split_at: [4, 1]");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+
+        let mapping = MAPPING_FRAMING_YAML.replace("coalesce: [msg2]", "chunk_size: 2");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_yaml_file_fails_to_deserialize_consecutive_swapped_sends() {
+        let swapped =
+            "chunk_delay_ms: 10\n              corrupt:\n                  swap_next: true";
+        let mapping = MAPPING_FRAMING_YAML.replace("chunk_delay_ms: 10", swapped);
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_ok!(parsed);
+
+        let mapping = mapping.replace(
+            "coalesce: [msg2]",
+            "coalesce: [msg2]\n              corrupt:\n                  swap_next: true",
+        );
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+    }
+
+    #[test]
+    fn test_yaml_file_fails_to_deserialize_zero_bandwidth() {
+        let impaired = "name: good test\n\n        impairment:\n            bandwidth: 1024";
+        let mapping = MAPPING_YAML.replace("name: good test", impaired);
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_ok!(parsed);
+
+        let mapping = mapping.replace("bandwidth: 1024", "bandwidth: 0");
+        let parsed: Result<MappingFile, serde_yaml::Error> = serde_yaml::from_str(&mapping);
+        assert_err!(parsed);
+    }
 }
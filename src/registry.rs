@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::connection::{ConsoleEvent, Control, Observers, Writer};
+
+/// Write half of a connection, shared so other connections can write to it
+pub(crate) type SharedWriter = Arc<tokio::sync::Mutex<Writer>>;
+
+/// Keeps track of the live connections of a server
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    conns: Mutex<BTreeMap<usize, Entry>>,
+    /// Events of the connections driven from the console, once it listens
+    console: Mutex<Option<mpsc::UnboundedSender<(usize, ConsoleEvent)>>>,
+}
+
+/// A live connection, by its accept order in the registry
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub peer: Option<SocketAddr>,
+    pub group: Option<String>,
+    pub writer: SharedWriter,
+    pub observers: Observers,
+    pub progress: Progress,
+    /// Commands to the connection, when driven from the console
+    pub control: Option<mpsc::UnboundedSender<Control>>,
+}
+
+/// Where a connection is in its scenario
+#[derive(Debug, Default, Clone, Serialize)]
+pub(crate) struct Progress {
+    pub scenario: Option<String>,
+    pub state: Option<String>,
+    pub step: Option<String>,
+}
+
+impl Registry {
+    /// Registers the live connection of given accept order.
+    pub(crate) fn register(
+        &self,
+        index: usize,
+        peer: Option<SocketAddr>,
+        writer: SharedWriter,
+        observers: Observers,
+    ) {
+        let entry = Entry {
+            peer,
+            group: None,
+            writer,
+            observers,
+            progress: Progress::default(),
+            control: None,
+        };
+
+        self.conns.lock().unwrap().insert(index, entry);
+    }
+
+    /// Removes a connection, once it is finished.
+    pub(crate) fn unregister(&self, index: usize) {
+        self.conns.lock().unwrap().remove(&index);
+    }
+
+    /// Adds a connection to a named group.
+    pub(crate) fn join(&self, index: usize, group: &str) {
+        if let Some(entry) = self.conns.lock().unwrap().get_mut(&index) {
+            entry.group = Some(group.to_string());
+        }
+    }
+
+    /// Lets a connection be driven from the console, through `control`.
+    pub(crate) fn set_control(&self, index: usize, control: mpsc::UnboundedSender<Control>) {
+        if let Some(entry) = self.conns.lock().unwrap().get_mut(&index) {
+            entry.control = Some(control);
+        }
+    }
+
+    /// Listens to the events of the connections driven from the console.
+    pub(crate) fn console_events(&self) -> mpsc::UnboundedReceiver<(usize, ConsoleEvent)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.console.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// Tells the console about an event of a connection, if it listens.
+    pub(crate) fn to_console(&self, index: usize, event: ConsoleEvent) {
+        if let Some(console) = self.console.lock().unwrap().as_ref() {
+            let _ = console.send((index, event));
+        }
+    }
+
+    /// Updates where a connection is in its scenario.
+    pub(crate) fn progress(&self, index: usize, update: impl FnOnce(&mut Progress)) {
+        if let Some(entry) = self.conns.lock().unwrap().get_mut(&index) {
+            update(&mut entry.progress);
+        }
+    }
+
+    /// Live connection of given accept order, if any.
+    pub(crate) fn entry(&self, index: usize) -> Option<Entry> {
+        self.conns.lock().unwrap().get(&index).cloned()
+    }
+
+    /// Live connections, in accept order, optionally only the ones in `group`.
+    pub(crate) fn entries(&self, group: Option<&str>) -> Vec<(usize, Entry)> {
+        self.conns
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| group.is_none() || entry.group.as_deref() == group)
+            .map(|(index, entry)| (*index, entry.clone()))
+            .collect()
+    }
+}
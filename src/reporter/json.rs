@@ -0,0 +1,35 @@
+use serde::Serialize;
+use std::io::{self, Write};
+
+use super::{ConnectionReport, Outcome, ReportWriter, Summary};
+
+/// Writes a JSON report, with the timing and bytes exchanged of every step
+pub(super) struct JsonWriter;
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    outcome: Outcome,
+    summary: &'a Summary,
+    connections: &'a [&'a ConnectionReport],
+}
+
+impl ReportWriter for JsonWriter {
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn write(
+        &self,
+        connections: &[&ConnectionReport],
+        summary: &Summary,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let report = JsonReport {
+            outcome: summary.outcome(),
+            summary,
+            connections,
+        };
+
+        serde_json::to_writer_pretty(out, &report).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,73 @@
+use junit_report::Duration as JUnitDuration;
+use junit_report::{OffsetDateTime, ReportBuilder, TestCaseBuilder, TestSuite, TestSuiteBuilder};
+use std::io::{self, Write};
+
+use super::{ConnectionReport, ReportWriter, Status, Summary};
+
+/// Writes a JUnit XML report, with a test suite per connection
+pub(super) struct JUnitWriter;
+
+impl ReportWriter for JUnitWriter {
+    fn extension(&self) -> &'static str {
+        "xml"
+    }
+
+    fn write(
+        &self,
+        connections: &[&ConnectionReport],
+        _summary: &Summary,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        ReportBuilder::new()
+            .add_testsuites(connections.iter().map(|conn| test_suite(conn)))
+            .build()
+            .write_xml(out)
+            .map_err(io::Error::other)
+    }
+}
+
+fn test_suite(conn: &ConnectionReport) -> TestSuite {
+    let mut builder = TestSuiteBuilder::new(&conn.name);
+    builder.set_timestamp(OffsetDateTime::from(conn.started));
+    if let Some(transcript) = &conn.transcript {
+        builder.set_system_out(transcript);
+    }
+    // JUnit has no place for metrics, the latency of the client is kept as the errors output
+    if let Some(stats) = conn.latencies().stats() {
+        builder.set_system_err(&format!("client latency: {}", stats));
+    }
+
+    for step in &conn.steps {
+        let duration = JUnitDuration::milliseconds(step.duration.as_millis() as i64);
+        let mut test_case = match &step.status {
+            Status::Passed => TestCaseBuilder::success(&step.name, duration),
+            Status::Failed {
+                kind,
+                message,
+                output,
+            } => {
+                let mut test_case = TestCaseBuilder::failure(&step.name, duration, kind, message);
+                if let Some(output) = output {
+                    test_case.set_system_out(output);
+                }
+                test_case
+            }
+            Status::Errored { kind, message } => {
+                TestCaseBuilder::error(&step.name, duration, kind, message)
+            }
+            // JUnit has no place for the reason, it is kept as the output
+            Status::Skipped { reason } => {
+                let mut test_case = TestCaseBuilder::skipped(&step.name);
+                test_case.set_system_out(reason);
+                test_case
+            }
+        };
+
+        if let Some(classname) = &step.classname {
+            test_case.set_classname(classname);
+        }
+        builder.add_testcase(test_case.build());
+    }
+
+    builder.build()
+}
@@ -0,0 +1,99 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use tokio::time::Duration;
+
+/// Response latencies of a client, from a `Send` to the next matching `Recv`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Latencies {
+    samples: Vec<Duration>,
+}
+
+/// Statistics of latencies, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Latencies {
+    pub fn push(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    pub fn extend(&mut self, other: &Latencies) {
+        self.samples.extend(&other.samples);
+    }
+
+    /// Statistics of the samples, if any.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+
+        Some(LatencyStats {
+            count: sorted.len(),
+            min_ms: as_ms(sorted[0]),
+            avg_ms: as_ms(total / sorted.len() as u32),
+            p50_ms: as_ms(percentile(&sorted, 50)),
+            p99_ms: as_ms(percentile(&sorted, 99)),
+            max_ms: as_ms(sorted[sorted.len() - 1]),
+        })
+    }
+}
+
+impl Serialize for Latencies {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.stats().serialize(serializer)
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} samples, min {:.3} ms, avg {:.3} ms, p50 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+            self.count, self.min_ms, self.avg_ms, self.p50_ms, self.p99_ms, self.max_ms
+        )
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len()).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_use_nearest_rank_percentiles() {
+        let mut latencies = Latencies::default();
+        assert_eq!(latencies.stats(), None);
+
+        for ms in (1..=100).rev() {
+            latencies.push(Duration::from_millis(ms));
+        }
+        let stats = latencies.stats().unwrap();
+
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.avg_ms, 50.5);
+        assert_eq!(stats.p50_ms, 50.0);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert_eq!(stats.max_ms, 100.0);
+    }
+}
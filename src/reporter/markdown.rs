@@ -0,0 +1,83 @@
+use std::io::{self, Write};
+
+use super::{ConnectionReport, ReportWriter, Status, Summary};
+
+/// Writes a Markdown summary, with a table of steps per connection
+pub(super) struct MarkdownWriter;
+
+impl ReportWriter for MarkdownWriter {
+    fn extension(&self) -> &'static str {
+        "md"
+    }
+
+    fn write(
+        &self,
+        connections: &[&ConnectionReport],
+        summary: &Summary,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        writeln!(
+            out,
+            "**{:?}**: {} connections, {} tests, {} failures, {} errors, {} skipped",
+            summary.outcome(),
+            summary.connections,
+            summary.tests,
+            summary.failures,
+            summary.errors,
+            summary.skipped
+        )?;
+        if let Some(stats) = summary.latency.stats() {
+            writeln!(out)?;
+            writeln!(out, "Client latency: {}", stats)?;
+        }
+
+        for conn in connections {
+            writeln!(out)?;
+            writeln!(out, "### {}", conn.name)?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} bytes sent, {} bytes received in {:.1} ms",
+                conn.bytes_sent,
+                conn.bytes_received,
+                conn.duration.as_secs_f64() * 1000.0
+            )?;
+            if let Some(stats) = conn.latencies().stats() {
+                writeln!(out)?;
+                writeln!(out, "Client latency: {}", stats)?;
+            }
+            writeln!(out)?;
+            writeln!(out, "| Step | Result | Time (ms) | Details |")?;
+            writeln!(out, "|------|--------|-----------|---------|")?;
+
+            for step in &conn.steps {
+                let (result, details) = match &step.status {
+                    Status::Passed => ("passed", String::new()),
+                    Status::Failed { kind, message, .. } => {
+                        ("failed", format!("{}: {}", kind, message))
+                    }
+                    Status::Errored { kind, message } => {
+                        ("errored", format!("{}: {}", kind, message))
+                    }
+                    Status::Skipped { reason } => ("skipped", reason.clone()),
+                };
+
+                writeln!(
+                    out,
+                    "| {} | {} | {:.1} | {} |",
+                    escape(&step.name),
+                    result,
+                    step.duration.as_secs_f64() * 1000.0,
+                    escape(&details)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes the characters breaking a table cell
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
@@ -0,0 +1,828 @@
+mod json;
+mod junit;
+mod latency;
+mod markdown;
+mod tap;
+
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::time::{Duration, Instant};
+use tracing::error;
+
+use crate::connection::Observers;
+use crate::journal::{Journal, SharedJournal};
+use crate::transcript::SharedTranscript;
+
+pub use latency::{Latencies, LatencyStats};
+
+/// Placeholder of the connection index in a report path
+const CONN_PLACEHOLDER: &str = "{conn}";
+
+/// Records the results of the actions of a connection
+#[derive(Debug)]
+pub struct Reporter {
+    report: ConnectionReport,
+    start: Instant,
+    classname: Option<String>,
+
+    /// Bytes exchanged by the connection, and how many were already counted
+    traffic: Option<Arc<Traffic>>,
+    counted: (u64, u64),
+
+    transcript: Option<SharedTranscript>,
+    journal: Option<SharedJournal>,
+}
+
+#[derive(Debug)]
+pub enum TestResult {
+    Success,
+    Error,
+    Failure,
+}
+
+/// Counts of test results, of a single connection or a whole run
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub connections: usize,
+    pub tests: usize,
+    pub failures: usize,
+    pub errors: usize,
+    pub skipped: usize,
+    pub latency: Latencies,
+}
+
+/// Overall outcome of a run, also the process exit code
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// All expectations were met
+    Passed = 0,
+    /// Some expectation was not met
+    Failed = 1,
+    /// Some action errored or was interrupted
+    Errored = 2,
+    /// No client connected to the server
+    NoClient = 3,
+}
+
+/// Format of a written report
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    Junit,
+    Json,
+    Tap,
+    Markdown,
+}
+
+/// Writes the reports of connections in some format
+trait ReportWriter {
+    /// Extension of the files written
+    fn extension(&self) -> &'static str;
+
+    fn write(
+        &self,
+        connections: &[&ConnectionReport],
+        summary: &Summary,
+        out: &mut dyn Write,
+    ) -> io::Result<()>;
+}
+
+/// Bytes sent and received by a connection
+#[derive(Debug, Default)]
+pub struct Traffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Results of the actions of a connection, along with its metadata
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionReport {
+    pub index: usize,
+    pub name: String,
+    pub peer: Option<SocketAddr>,
+    pub local: Option<SocketAddr>,
+    pub scenario: Option<String>,
+    #[serde(rename = "started_ms", serialize_with = "since_epoch_ms")]
+    pub started: SystemTime,
+    #[serde(rename = "duration_ms", serialize_with = "as_ms")]
+    pub duration: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub steps: Vec<Step>,
+    pub journal: Journal,
+    #[serde(skip)]
+    pub transcript: Option<String>,
+}
+
+/// Results of a whole run: the summary and the report of each connection
+#[derive(Debug, Default, Clone)]
+pub struct RunReport {
+    pub summary: Summary,
+    pub connections: Vec<ConnectionReport>,
+    /// Report files that could not be written, with the reason
+    pub report_errors: Vec<String>,
+}
+
+/// Result of a single action
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+    pub name: String,
+    pub classname: Option<String>,
+    #[serde(rename = "duration_ms", serialize_with = "as_ms")]
+    pub duration: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Time from the previous `Send` to this message being received
+    #[serde(
+        rename = "latency_ms",
+        serialize_with = "as_optional_ms",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub latency: Option<Duration>,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    Failed {
+        #[serde(rename = "type")]
+        kind: String,
+        message: String,
+        output: Option<String>,
+    },
+    Errored {
+        #[serde(rename = "type")]
+        kind: String,
+        message: String,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Passed => "passed".fmt(fmt),
+            Status::Failed { kind, message, .. } => write!(fmt, "failed, {}: {}", kind, message),
+            Status::Errored { kind, message } => write!(fmt, "errored, {}: {}", kind, message),
+            Status::Skipped { reason } => write!(fmt, "skipped, {}", reason),
+        }
+    }
+}
+
+impl Traffic {
+    pub fn sent(&self, len: usize) {
+        self.sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, len: usize) {
+        self.received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn totals(&self) -> (u64, u64) {
+        (
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl Summary {
+    /// Adds the counts of `other` to this summary.
+    pub fn add(&mut self, other: &Summary) {
+        self.connections += other.connections;
+        self.tests += other.tests;
+        self.failures += other.failures;
+        self.errors += other.errors;
+        self.skipped += other.skipped;
+        self.latency.extend(&other.latency);
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        if self.connections == 0 {
+            Outcome::NoClient
+        } else if self.errors > 0 {
+            Outcome::Errored
+        } else if self.failures > 0 {
+            Outcome::Failed
+        } else {
+            Outcome::Passed
+        }
+    }
+}
+
+impl ConnectionReport {
+    /// Counts of the results of this connection
+    pub fn summary(&self) -> Summary {
+        let count = |matches: fn(&Status) -> bool| {
+            self.steps
+                .iter()
+                .filter(|step| matches(&step.status))
+                .count()
+        };
+
+        Summary {
+            connections: 1,
+            tests: self.steps.len(),
+            failures: count(|status| matches!(status, Status::Failed { .. })),
+            errors: count(|status| matches!(status, Status::Errored { .. })),
+            skipped: count(|status| matches!(status, Status::Skipped { .. })),
+            latency: self.latencies(),
+        }
+    }
+
+    /// Response latencies of the client, of all steps that measured one
+    pub fn latencies(&self) -> Latencies {
+        let mut latencies = Latencies::default();
+        for latency in self.steps.iter().filter_map(|step| step.latency) {
+            latencies.push(latency);
+        }
+        latencies
+    }
+}
+
+impl RunReport {
+    /// Outcome of the connections, errored if a report could not be written,
+    /// as CI can not tell how the clients did without it.
+    pub fn outcome(&self) -> Outcome {
+        match self.report_errors.is_empty() {
+            true => self.summary.outcome(),
+            false => Outcome::Errored,
+        }
+    }
+
+    /// Report of the connection of given accept order, if it finished.
+    pub fn connection(&self, index: usize) -> Option<&ConnectionReport> {
+        self.connections.iter().find(|conn| conn.index == index)
+    }
+
+    /// Times the message `name` was received as expected, on all connections.
+    pub fn received(&self, name: &str) -> usize {
+        self.connections
+            .iter()
+            .map(|conn| conn.journal.received(name))
+            .sum()
+    }
+}
+
+impl ReportFormat {
+    fn writer(&self) -> Box<dyn ReportWriter> {
+        match self {
+            ReportFormat::Junit => Box::new(junit::JUnitWriter),
+            ReportFormat::Json => Box::new(json::JsonWriter),
+            ReportFormat::Tap => Box::new(tap::TapWriter),
+            ReportFormat::Markdown => Box::new(markdown::MarkdownWriter),
+        }
+    }
+}
+
+impl Reporter {
+    pub fn new(suite: &str) -> Reporter {
+        Reporter {
+            report: ConnectionReport {
+                index: 0,
+                name: suite.to_string(),
+                peer: None,
+                local: None,
+                scenario: None,
+                started: SystemTime::now(),
+                duration: Duration::ZERO,
+                bytes_sent: 0,
+                bytes_received: 0,
+                steps: vec![],
+                journal: Journal::default(),
+                transcript: None,
+            },
+            start: Instant::now(),
+            classname: None,
+            traffic: None,
+            counted: (0, 0),
+            transcript: None,
+            journal: None,
+        }
+    }
+
+    /// Sets the metadata of the connection reported
+    pub(crate) fn set_connection(
+        &mut self,
+        index: usize,
+        peer: Option<SocketAddr>,
+        local: Option<SocketAddr>,
+        observers: Observers,
+    ) {
+        self.report.index = index;
+        self.report.peer = peer;
+        self.report.local = local;
+        self.traffic = Some(observers.traffic);
+        self.transcript = Some(observers.transcript);
+        self.journal = Some(observers.journal);
+    }
+
+    /// Sets the name of the scenario selected for the connection
+    pub fn set_scenario(&mut self, scenario: &str) {
+        self.report.scenario = Some(scenario.to_string());
+    }
+
+    /// Sets the class name of the next test cases, like the current scenario state
+    pub fn set_classname(&mut self, classname: Option<&str>) {
+        self.classname = classname.map(String::from);
+    }
+
+    /// Creates a sucess test case in the current report
+    pub fn sucess(&mut self, name: &str, duration: Duration) {
+        self.add_step(name, duration, Status::Passed);
+    }
+
+    /// Sets the response latency of the client on the last test case, the time
+    /// from the previous message sent to the one received
+    pub fn set_latency(&mut self, latency: Duration) {
+        if let Some(step) = self.report.steps.last_mut() {
+            step.latency = Some(latency);
+        }
+    }
+
+    /// Creates a failure test case in the current report
+    pub fn failure(&mut self, name: &str, duration: Duration, error_type: &str, message: &str) {
+        let status = Status::Failed {
+            kind: error_type.to_string(),
+            message: message.to_string(),
+            output: None,
+        };
+
+        self.add_step(name, duration, status);
+    }
+
+    /// Creates a failure test case in the current report, with details of what
+    /// went wrong in its output
+    pub fn failure_with_output(
+        &mut self,
+        name: &str,
+        duration: Duration,
+        error_type: &str,
+        message: &str,
+        output: &str,
+    ) {
+        let status = Status::Failed {
+            kind: error_type.to_string(),
+            message: message.to_string(),
+            output: Some(output.to_string()),
+        };
+
+        self.add_step(name, duration, status);
+    }
+
+    /// Creates a error test case in the current report
+    pub fn error(&mut self, name: &str, duration: Duration, error_type: &str, message: &str) {
+        let status = Status::Errored {
+            kind: error_type.to_string(),
+            message: message.to_string(),
+        };
+
+        self.add_step(name, duration, status);
+    }
+
+    /// Creates a skipped test case in the current report, with the reason
+    pub fn skipped(&mut self, name: &str, reason: &str) {
+        let status = Status::Skipped {
+            reason: reason.to_string(),
+        };
+
+        self.add_step(name, Duration::ZERO, status);
+    }
+
+    /// Number of test cases reported so far
+    pub fn step_count(&self) -> usize {
+        self.report.steps.len()
+    }
+
+    /// Counts of the test cases reported so far
+    pub fn summary(&self) -> Summary {
+        self.report.summary()
+    }
+
+    /// Report of the connection so far, with its totals up to now
+    pub fn connection_report(&self) -> ConnectionReport {
+        let mut report = self.report.clone();
+        report.duration = self.start.elapsed();
+        if let Some(traffic) = &self.traffic {
+            (report.bytes_sent, report.bytes_received) = traffic.totals();
+        }
+        if let Some(transcript) = &self.transcript {
+            report.transcript = Some(transcript.lock().unwrap().render());
+        }
+        if let Some(journal) = &self.journal {
+            report.journal = journal.lock().unwrap().clone();
+        }
+
+        report
+    }
+
+    /// Dump the final report in XML format in given `output_path`
+    pub fn report(&self, output_path: &str) -> io::Result<()> {
+        let report = self.connection_report();
+        write_report(
+            output_path,
+            ReportFormat::Junit,
+            &[&report],
+            &report.summary(),
+        )
+    }
+
+    /// Adds a step, with the bytes exchanged since the previous one.
+    fn add_step(&mut self, name: &str, duration: Duration, status: Status) {
+        let (sent, received) = match &self.traffic {
+            Some(traffic) => traffic.totals(),
+            None => (0, 0),
+        };
+        if let Some(transcript) = &self.transcript {
+            transcript
+                .lock()
+                .unwrap()
+                .note(format!("step '{}': {}", name, status));
+        }
+
+        self.report.steps.push(Step {
+            name: name.to_string(),
+            classname: self.classname.clone(),
+            duration,
+            bytes_sent: sent - self.counted.0,
+            bytes_received: received - self.counted.1,
+            latency: None,
+            status,
+        });
+        self.counted = (sent, received);
+    }
+}
+
+/// Collects the reports of all connections of a server, as they finish.
+///
+/// Connections are written together, unless the report path has a `{conn}`
+/// placeholder, then each one is written to its own file. With several formats,
+/// the extension of the path is replaced by the one of each format.
+#[derive(Debug)]
+pub struct Reports {
+    path: String,
+    formats: Vec<ReportFormat>,
+    transcript_dir: Option<PathBuf>,
+    connections: BTreeMap<usize, ConnectionReport>,
+    summary: Summary,
+    /// Last error writing each report file, until it is written successfully
+    write_errors: BTreeMap<String, String>,
+}
+
+impl Reports {
+    pub fn new(path: &str, formats: &[ReportFormat]) -> Reports {
+        Reports {
+            path: path.to_string(),
+            formats: formats.to_vec(),
+            transcript_dir: None,
+            connections: BTreeMap::new(),
+            summary: Summary::default(),
+            write_errors: BTreeMap::new(),
+        }
+    }
+
+    /// Also writes the transcript of each connection to a file in `dir`.
+    pub fn set_transcript_dir(&mut self, dir: &str) {
+        self.transcript_dir = Some(PathBuf::from(dir));
+    }
+
+    /// Adds the report of the connection of given accept order, writing it out.
+    ///
+    /// The connection is added even if writing the report fails, which makes
+    /// the run errored until the file is written. Failing to write its
+    /// transcript is only logged, it never holds up the report.
+    pub fn add(&mut self, index: usize, reporter: &Reporter) -> io::Result<()> {
+        let report = reporter.connection_report();
+        let summary = report.summary();
+        self.summary.add(&summary);
+        self.connections.insert(index, report);
+        let report = &self.connections[&index];
+
+        let written = if self.path.contains(CONN_PLACEHOLDER) {
+            let path = self.path.replace(CONN_PLACEHOLDER, &index.to_string());
+            self.write(&path, &[report], &summary)
+        } else {
+            let connections: Vec<&ConnectionReport> = self.connections.values().collect();
+            self.write(&self.path, &connections, &self.summary)
+        };
+
+        if let (Some(dir), Some(transcript)) = (&self.transcript_dir, &report.transcript) {
+            let path = dir.join(format!("transcript-{}.txt", index));
+            if let Err(err) = fs::write(&path, format!("{}\n\n{}", report.name, transcript)) {
+                error!("could not write transcript {:?}: {:}", path, err);
+            }
+        }
+
+        self.record(written)
+    }
+
+    /// Writes a report without any connection, replacing one left by an
+    /// earlier run, so a run no client connects to has a report of its own.
+    ///
+    /// Nothing is written when each connection has its own file.
+    pub fn write_empty(&mut self) -> io::Result<()> {
+        if self.path.contains(CONN_PLACEHOLDER) {
+            return Ok(());
+        }
+
+        let written = self.write(&self.path, &[], &Summary::default());
+        self.record(written)
+    }
+
+    /// Keeps the errors writing report files, until each one is written.
+    fn record(&mut self, written: Vec<(String, io::Result<()>)>) -> io::Result<()> {
+        let mut result = Ok(());
+        for (path, written) in written {
+            match written {
+                Ok(()) => {
+                    self.write_errors.remove(&path);
+                }
+                Err(err) => {
+                    self.write_errors.insert(path, err.to_string());
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Counts of the results of all connections added so far
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Report of the finished connection of given accept order, if any
+    pub fn connection(&self, index: usize) -> Option<&ConnectionReport> {
+        self.connections.get(&index)
+    }
+
+    /// Results of all connections added so far
+    pub fn run_report(&self) -> RunReport {
+        RunReport {
+            summary: self.summary.clone(),
+            connections: self.connections.values().cloned().collect(),
+            report_errors: self
+                .write_errors
+                .iter()
+                .map(|(path, err)| format!("{}: {}", path, err))
+                .collect(),
+        }
+    }
+
+    /// Renders the reports of all connections added so far in `format`.
+    pub fn render(&self, format: ReportFormat) -> io::Result<Vec<u8>> {
+        let connections: Vec<&ConnectionReport> = self.connections.values().collect();
+        let mut out = vec![];
+        format
+            .writer()
+            .write(&connections, &self.summary, &mut out)?;
+
+        Ok(out)
+    }
+
+    /// Forgets the connections added so far, the report files are left as is.
+    pub fn reset(&mut self) {
+        self.connections.clear();
+        self.summary = Summary::default();
+    }
+
+    /// Writes the report in each format, returning the result for each file.
+    fn write(
+        &self,
+        path: &str,
+        connections: &[&ConnectionReport],
+        summary: &Summary,
+    ) -> Vec<(String, io::Result<()>)> {
+        let mut results = vec![];
+        for &format in &self.formats {
+            let path = match self.formats.len() {
+                1 => path.to_string(),
+                _ => Path::new(path)
+                    .with_extension(format.writer().extension())
+                    .to_string_lossy()
+                    .to_string(),
+            };
+
+            let written = write_report(&path, format, connections, summary);
+            results.push((path, written));
+        }
+
+        results
+    }
+}
+
+/// Writes the reports of `connections` to `path`, atomically, so a reader
+/// never sees it half written.
+fn write_report(
+    path: &str,
+    format: ReportFormat,
+    connections: &[&ConnectionReport],
+    summary: &Summary,
+) -> io::Result<()> {
+    let tmp_path = format!("{}.{}.tmp", path, std::process::id());
+    let mut tmp_file = File::create(&tmp_path)?;
+    format.writer().write(connections, summary, &mut tmp_file)?;
+    tmp_file.sync_all()?;
+
+    fs::rename(&tmp_path, Path::new(path))
+}
+
+fn as_ms<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+fn as_optional_ms<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => as_ms(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn since_epoch_ms<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    serializer.serialize_u64(since_epoch.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_outcome_prioritizes_errors_over_failures() {
+        let mut summary = Summary::default();
+        assert_eq!(summary.outcome(), Outcome::NoClient);
+
+        let mut reporter = Reporter::new("suite");
+        reporter.sucess("first", Duration::ZERO);
+        summary.add(&reporter.summary());
+        assert_eq!(summary.outcome(), Outcome::Passed);
+
+        reporter.failure("second", Duration::ZERO, "recv_error", "not recv");
+        summary.add(&reporter.summary());
+        assert_eq!(summary.outcome(), Outcome::Failed);
+
+        reporter.error("third", Duration::ZERO, "message_error", "reset");
+        summary.add(&reporter.summary());
+        assert_eq!(summary.outcome(), Outcome::Errored);
+        assert_eq!(summary.connections, 3);
+    }
+
+    #[test]
+    fn test_reports_collects_a_suite_per_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result.xml");
+        let mut reports = Reports::new(path.to_str().unwrap(), &[ReportFormat::Junit]);
+
+        for index in [1, 2] {
+            let mut reporter = Reporter::new(&format!("suite #{}", index));
+            match index {
+                1 => reporter.sucess("msg1", Duration::ZERO),
+                _ => reporter.error("msg1", Duration::ZERO, "message_error", "reset"),
+            }
+            reports.add(index, &reporter).unwrap();
+        }
+
+        let report = fs::read_to_string(&path).unwrap();
+        assert!(report.contains(r#"name="suite #1""#));
+        assert!(report.contains(r#"name="suite #2""#));
+        assert_eq!(reports.summary().outcome(), Outcome::Errored);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_reports_writes_templated_path_per_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result-{conn}.xml");
+        let mut reports = Reports::new(path.to_str().unwrap(), &[ReportFormat::Junit]);
+
+        for index in [1, 2] {
+            let reporter = Reporter::new(&format!("suite #{}", index));
+            reports.add(index, &reporter).unwrap();
+        }
+
+        let second = fs::read_to_string(dir.path().join("result-2.xml")).unwrap();
+        assert!(second.contains(r#"name="suite #2""#));
+        assert!(!second.contains(r#"name="suite #1""#));
+        assert!(dir.path().join("result-1.xml").exists());
+    }
+
+    #[test]
+    fn test_reports_writes_report_despite_transcript_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result.xml");
+        let mut reports = Reports::new(path.to_str().unwrap(), &[ReportFormat::Junit]);
+        reports.set_transcript_dir(&dir.path().join("missing").to_string_lossy());
+
+        let mut reporter = Reporter::new("suite #1");
+        reporter.set_connection(1, None, None, Observers::default());
+        reporter.sucess("msg1", Duration::ZERO);
+        reports.add(1, &reporter).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().contains("msg1"));
+        assert!(reports.connection(1).is_some());
+    }
+
+    #[test]
+    fn test_reports_errors_run_until_report_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out").join("result.xml");
+        let mut reports = Reports::new(path.to_str().unwrap(), &[ReportFormat::Junit]);
+
+        let mut reporter = Reporter::new("suite #1");
+        reporter.sucess("msg1", Duration::ZERO);
+        assert!(reports.add(1, &reporter).is_err());
+        assert_eq!(reports.summary().outcome(), Outcome::Passed);
+        assert_eq!(reports.run_report().outcome(), Outcome::Errored);
+        assert_eq!(reports.run_report().connections.len(), 1);
+
+        fs::create_dir(dir.path().join("out")).unwrap();
+        reports.add(2, &reporter).unwrap();
+        assert_eq!(reports.run_report().outcome(), Outcome::Passed);
+        assert!(fs::read_to_string(&path).unwrap().contains("suite #1"));
+    }
+
+    #[test]
+    fn test_reports_replaces_stale_report_with_empty_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result.xml");
+        fs::write(&path, "stale").unwrap();
+        let mut reports = Reports::new(path.to_str().unwrap(), &[ReportFormat::Junit]);
+
+        reports.write_empty().unwrap();
+
+        let report = fs::read_to_string(&path).unwrap();
+        assert!(report.contains("<testsuites"));
+        assert!(!report.contains("stale"));
+        assert_eq!(reports.run_report().outcome(), Outcome::NoClient);
+    }
+
+    #[test]
+    fn test_reports_writes_each_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("result.xml");
+        let formats = [
+            ReportFormat::Junit,
+            ReportFormat::Json,
+            ReportFormat::Tap,
+            ReportFormat::Markdown,
+        ];
+        let mut reports = Reports::new(path.to_str().unwrap(), &formats);
+
+        let observers = Observers::default();
+        let traffic = observers.traffic.clone();
+        let mut reporter = Reporter::new("suite #1");
+        reporter.set_connection(1, None, None, observers);
+        traffic.sent(5);
+        reporter.sucess("msg1", Duration::from_millis(2));
+        reporter.set_latency(Duration::from_millis(4));
+        traffic.received(3);
+        reporter.failure("msg2", Duration::ZERO, "recv_error", "not recv");
+        reporter.skipped("Shutdown", "not reached");
+        reports.add(1, &reporter).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("result.json")).unwrap())
+                .unwrap();
+        assert_eq!(json["outcome"], "failed");
+        assert_eq!(json["connections"][0]["bytes_received"], 3);
+        assert_eq!(json["connections"][0]["steps"][0]["bytes_sent"], 5);
+        assert_eq!(json["connections"][0]["steps"][0]["duration_ms"], 2.0);
+        assert_eq!(json["connections"][0]["steps"][0]["latency_ms"], 4.0);
+        assert_eq!(json["summary"]["latency"]["p99_ms"], 4.0);
+        assert_eq!(json["connections"][0]["steps"][1]["status"], "failed");
+        assert_eq!(json["connections"][0]["steps"][1]["bytes_received"], 3);
+
+        let tap = fs::read_to_string(dir.path().join("result.tap")).unwrap();
+        assert!(tap.starts_with("TAP version 13\n1..3\n"));
+        assert!(tap.contains("ok 1 - suite #1: msg1\n"));
+        assert!(tap.contains("not ok 2 - suite #1: msg2\n"));
+        assert!(tap.contains("ok 3 - suite #1: Shutdown # SKIP not reached\n"));
+        assert!(tap.contains("# client latency: 1 samples, min 4.000 ms"));
+
+        let markdown = fs::read_to_string(dir.path().join("result.md")).unwrap();
+        assert!(markdown.contains("| msg2 | failed | 0.0 | recv_error: not recv |"));
+
+        let junit = fs::read_to_string(&path).unwrap();
+        assert!(junit.contains(r#"failures="1""#));
+    }
+}
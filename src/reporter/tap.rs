@@ -0,0 +1,50 @@
+use std::io::{self, Write};
+
+use super::{ConnectionReport, ReportWriter, Status, Summary};
+
+/// Writes a TAP version 13 report, with a test point per step
+pub(super) struct TapWriter;
+
+impl ReportWriter for TapWriter {
+    fn extension(&self) -> &'static str {
+        "tap"
+    }
+
+    fn write(
+        &self,
+        connections: &[&ConnectionReport],
+        summary: &Summary,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        writeln!(out, "TAP version 13")?;
+        writeln!(out, "1..{}", summary.tests)?;
+
+        let steps = connections
+            .iter()
+            .flat_map(|conn| conn.steps.iter().map(move |step| (conn, step)));
+        for (number, (conn, step)) in (1..).zip(steps) {
+            let description = format!("{}: {}", conn.name, step.name);
+
+            match &step.status {
+                Status::Passed => writeln!(out, "ok {} - {}", number, description)?,
+                Status::Skipped { reason } => {
+                    writeln!(out, "ok {} - {} # SKIP {}", number, description, reason)?
+                }
+                Status::Failed { kind, message, .. } | Status::Errored { kind, message } => {
+                    writeln!(out, "not ok {} - {}", number, description)?;
+                    writeln!(out, "  ---")?;
+                    writeln!(out, "  type: {}", kind)?;
+                    writeln!(out, "  message: {:?}", message)?;
+                    writeln!(out, "  duration_ms: {}", step.duration.as_millis())?;
+                    writeln!(out, "  ...")?;
+                }
+            }
+        }
+
+        if let Some(stats) = summary.latency.stats() {
+            writeln!(out, "# client latency: {}", stats)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tracing::{debug, error, info};
+
+use crate::connection::write_to;
+use crate::hexdump::from_hex;
+use crate::journal::{FrameStatus, Journal};
+use crate::mapping::{MappingGuard, MappingState};
+use crate::registry::{Entry, Progress, Registry};
+use crate::reporter::{ReportFormat, Reports};
+
+/// Largest request body accepted, plenty for a mapping file
+const MAX_BODY: usize = 1 << 20;
+
+/// Handle on the state of a running server, controlled by the HTTP admin API
+/// and the console
+#[derive(Debug, Clone)]
+pub(crate) struct Admin {
+    pub mapping_guard: Arc<MappingGuard>,
+    pub registry: Arc<Registry>,
+    pub reports: Arc<Mutex<Reports>>,
+    pub shutdown_notify: Arc<Notify>,
+}
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Response of the admin API, with a JSON body
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+/// A live connection, as listed by the admin API
+#[derive(Debug, Serialize)]
+struct ConnectionInfo {
+    index: usize,
+    peer: Option<SocketAddr>,
+    group: Option<String>,
+    #[serde(flatten)]
+    progress: Progress,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+/// Data to send to a live connection, either a mapped message or raw bytes
+#[derive(Debug, Deserialize)]
+struct SendRequest {
+    message: Option<String>,
+    hex: Option<String>,
+}
+
+/// Serves the admin API on `listener`, a request per connection, until the
+/// task running it is aborted.
+pub(crate) async fn serve(listener: TcpListener, admin: Admin) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                debug!("admin request from {:?}", peer);
+                let admin = admin.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = admin.handle(socket).await {
+                        error!("admin request failed: {:}", err);
+                    }
+                });
+            }
+            Err(err) => error!("could not accept admin connection: {:}", err),
+        }
+    }
+}
+
+impl Admin {
+    async fn handle(&self, mut socket: TcpStream) -> io::Result<()> {
+        let (reader, mut writer) = socket.split();
+        let response = match read_request(&mut BufReader::new(reader)).await? {
+            Some(request) => self.route(request).await,
+            None => Response::error(400, "malformed request"),
+        };
+
+        writer.write_all(&response.to_bytes()).await?;
+        writer.shutdown().await
+    }
+
+    async fn route(&self, request: Request) -> Response {
+        info!("admin {} {}", request.method, request.path);
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["connections"]) => self.connections(),
+            ("POST", ["connections", index, "send"]) => self.send(index, &request.body).await,
+            ("GET", ["connections", index, "journal"]) => self.journal(index),
+            ("GET", ["verify"]) => self.verify(),
+            ("PUT", ["mapping"]) => self.load_mapping(&request.body),
+            ("POST", ["reset"]) => self.reset(),
+            ("GET", ["results"]) => self.results(),
+            ("POST", ["shutdown"]) => self.shutdown(),
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    /// Lists the live connections and where they are in their scenario.
+    fn connections(&self) -> Response {
+        let connections: Vec<ConnectionInfo> = self
+            .registry
+            .entries(None)
+            .into_iter()
+            .map(|(index, entry)| {
+                let (bytes_sent, bytes_received) = entry.observers.traffic.totals();
+                ConnectionInfo {
+                    index,
+                    peer: entry.peer,
+                    group: entry.group,
+                    progress: entry.progress,
+                    bytes_sent,
+                    bytes_received,
+                }
+            })
+            .collect();
+
+        Response::json(200, &connections)
+    }
+
+    /// Sends a mapped message or raw bytes to a live connection, out of its scenario.
+    async fn send(&self, index: &str, body: &[u8]) -> Response {
+        let Some(entry) = index
+            .parse()
+            .ok()
+            .and_then(|index| self.registry.entry(index))
+        else {
+            return Response::error(404, format!("no live connection {}", index));
+        };
+        let request: SendRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(err) => return Response::error(400, err),
+        };
+
+        let data = match (request.message, request.hex) {
+            (Some(name), None) => match self.message(&name).await {
+                Some(message) => message.to_vec(),
+                None => return Response::error(404, format!("no message '{}' mapped", name)),
+            },
+            (None, Some(hex)) => match from_hex(&hex) {
+                Some(data) => data,
+                None => return Response::error(400, "invalid hex"),
+            },
+            _ => return Response::error(400, "expected either a message or hex"),
+        };
+
+        if let Err(err) = inject(&entry, &data).await {
+            return Response::error(500, err);
+        }
+        info!("sent {} bytes to connection {}", data.len(), index);
+
+        Response::json(200, &json!({ "sent": data.len() }))
+    }
+
+    /// Message of given name in the current mapping, if any.
+    pub(crate) async fn message(&self, name: &str) -> Option<Bytes> {
+        let state = self.mapping_guard.mapping().state;
+        let message = state.read().await.name_to_message.get(name).cloned();
+        message
+    }
+
+    /// Frames received by a connection, live or finished.
+    fn journal(&self, index: &str) -> Response {
+        let journals = self.journals();
+        match index.parse().ok().and_then(|index| journals.get(&index)) {
+            Some(journal) => Response::json(200, journal),
+            None => Response::error(404, format!("no connection {}", index)),
+        }
+    }
+
+    /// Times each message was received as expected, and how many frames were
+    /// not, on all connections, live or finished.
+    fn verify(&self) -> Response {
+        let mut received: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut unexpected = 0;
+
+        let journals = self.journals();
+        for entry in journals.values().flat_map(Journal::entries) {
+            match (entry.status, &entry.message) {
+                (FrameStatus::Matched, Some(message)) => *received.entry(message).or_default() += 1,
+                (FrameStatus::Matched, None) => {}
+                _ => unexpected += 1,
+            }
+        }
+
+        Response::json(
+            200,
+            &json!({ "received": received, "unexpected": unexpected }),
+        )
+    }
+
+    /// Journals of the live and finished connections, by accept order.
+    fn journals(&self) -> BTreeMap<usize, Journal> {
+        let mut journals: BTreeMap<usize, Journal> = self
+            .registry
+            .entries(None)
+            .into_iter()
+            .map(|(index, entry)| (index, entry.observers.journal.lock().unwrap().clone()))
+            .collect();
+
+        // Finished connections may still be registered, their report is final
+        for conn in self.reports.lock().unwrap().run_report().connections {
+            journals.insert(conn.index, conn.journal);
+        }
+
+        journals
+    }
+
+    /// Replaces the mapping of new connections, if the given one is valid.
+    fn load_mapping(&self, body: &[u8]) -> Response {
+        let content = String::from_utf8_lossy(body);
+
+        let loaded = MappingState::from_yaml(&content).and_then(|state| {
+            let name = state.mapping_name.clone();
+            self.mapping_guard.replace(state).map(|()| name)
+        });
+        match loaded {
+            Ok(name) => {
+                info!("mapping '{}' loaded", name);
+                Response::json(200, &json!({ "name": name }))
+            }
+            Err(err) => Response::error(400, format!("{:#}", err)),
+        }
+    }
+
+    /// Forgets the results and signals so far, for a fresh run.
+    fn reset(&self) -> Response {
+        self.reports.lock().unwrap().reset();
+        self.mapping_guard.mapping().signals.reset();
+
+        Response::json(200, &json!({}))
+    }
+
+    /// Results of the connections finished so far, as in the JSON report.
+    fn results(&self) -> Response {
+        match self.reports.lock().unwrap().render(ReportFormat::Json) {
+            Ok(body) => Response { status: 200, body },
+            Err(err) => Response::error(500, err),
+        }
+    }
+
+    fn shutdown(&self) -> Response {
+        info!("shutdown requested by admin");
+        self.shutdown_notify.notify_waiters();
+
+        Response::json(200, &json!({}))
+    }
+}
+
+/// Sends `data` to a live connection, out of its scenario.
+pub(crate) async fn inject(entry: &Entry, data: &[u8]) -> io::Result<()> {
+    write_to(&entry.writer, data).await
+}
+
+impl Response {
+    fn json(status: u16, body: &impl Serialize) -> Response {
+        Response {
+            status,
+            body: serde_json::to_vec(body).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: impl fmt::Display) -> Response {
+        Response::json(status, &json!({ "error": message.to_string() }))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            _ => "Internal Server Error",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason,
+            self.body.len()
+        );
+
+        [head.as_bytes(), &self.body].concat()
+    }
+}
+
+/// Reads an HTTP/1.1 request, with its body if it has a `Content-Length`.
+/// Returns `None` if the request is malformed.
+async fn read_request(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+
+    let mut request_line = line.split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(len) if len <= MAX_BODY => content_length = len,
+                    _ => return Ok(None),
+                }
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(Request { method, path, body }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request_with_body() {
+        let raw = b"PUT /mapping?dry=1 HTTP/1.1\r\nHost: x\r\ncontent-length: 5\r\n\r\nHello";
+        let request = read_request(&mut &raw[..]).await.unwrap().unwrap();
+
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/mapping");
+        assert_eq!(request.body, b"Hello");
+
+        let raw = b"GET /results HTTP/1.1\r\nContent-Length: nope\r\n\r\n";
+        assert!(read_request(&mut &raw[..]).await.unwrap().is_none());
+    }
+}
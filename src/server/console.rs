@@ -0,0 +1,334 @@
+use std::io::{self, BufRead};
+use std::thread;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::connection::{ConsoleEvent, Control};
+use crate::hexdump::{from_hex, hexdump};
+use crate::registry::Entry;
+
+use super::admin::{inject, Admin};
+
+const HELP: &str = "\
+commands:
+  list               live connections
+  use <index>        select the connection the commands apply to, the last one by default
+  send <message>     send a mapped message
+  hex <bytes>        send raw bytes, e.g. hex 48 65 6c 6c 6f
+  close              close the connection
+  reset              reset the connection
+  scenario [<name>]  run a mapped scenario on the connection, the top level one without a name
+  shutdown           shut the server down
+  help               show this help";
+
+/// A command typed in the console
+#[derive(Debug, PartialEq)]
+enum Command {
+    List,
+    Use(usize),
+    Send(String),
+    Hex(Vec<u8>),
+    Control(Control),
+    Shutdown,
+    Help,
+}
+
+/// Drives connections by hand, from commands typed by an operator
+#[derive(Debug)]
+pub(crate) struct Console {
+    admin: Admin,
+
+    /// Connection selected with `use`, if any
+    current: Option<usize>,
+}
+
+/// Reads commands from stdin until it is closed, printing their output along
+/// with the `events` of the connections.
+pub(crate) async fn run(admin: Admin, mut events: mpsc::UnboundedReceiver<(usize, ConsoleEvent)>) {
+    let mut console = Console {
+        admin,
+        current: None,
+    };
+    println!("{}", HELP);
+
+    // A blocking read of stdin can not be cancelled, so it gets a thread of its
+    // own, not holding up the runtime on exit
+    let (lines_tx, mut lines) = mpsc::channel(1);
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if lines_tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let output = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => console.execute(&line).await,
+                None => break,
+            },
+            Some((index, event)) = events.recv() => show(index, &event),
+        };
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    }
+    info!("console closed");
+}
+
+/// Describes an event of the connection of given index.
+fn show(index: usize, event: &ConsoleEvent) -> String {
+    match event {
+        ConsoleEvent::Connected(Some(peer)) => format!("[#{}] connected from {}", index, peer),
+        ConsoleEvent::Connected(None) => format!("[#{}] connected", index),
+        ConsoleEvent::Received(data) => {
+            format!("[#{}] recv {} bytes\n{}", index, data.len(), hexdump(data))
+                .trim_end()
+                .to_string()
+        }
+        ConsoleEvent::ClosedByPeer => format!("[#{}] closed by peer", index),
+        ConsoleEvent::Done(Control::Close) => format!("[#{}] closed", index),
+        ConsoleEvent::Done(Control::Reset) => format!("[#{}] reset", index),
+        ConsoleEvent::Done(Control::Scenario(None)) => {
+            format!("[#{}] running the top level scenario", index)
+        }
+        ConsoleEvent::Done(Control::Scenario(Some(name))) => {
+            format!("[#{}] running scenario '{}'", index, name)
+        }
+        ConsoleEvent::NoScenario(name) => format!("[#{}] no scenario '{}' mapped", index, name),
+    }
+}
+
+impl Console {
+    /// Executes a line typed in the console, returning what to print.
+    async fn execute(&mut self, line: &str) -> String {
+        let command = match parse(line) {
+            Ok(Some(command)) => command,
+            Ok(None) => return String::new(),
+            Err(err) => return err,
+        };
+
+        match command {
+            Command::List => self.list(),
+            Command::Use(index) => match self.admin.registry.entry(index) {
+                Some(_) => {
+                    self.current = Some(index);
+                    format!("using connection {}", index)
+                }
+                None => format!("no live connection {}", index),
+            },
+            Command::Send(name) => match self.admin.message(&name).await {
+                Some(message) => self.send(&message).await,
+                None => format!("no message '{}' mapped", name),
+            },
+            Command::Hex(data) => self.send(&data).await,
+            Command::Control(control) => match self.target() {
+                Ok((index, entry)) => match entry.control {
+                    Some(tx) if tx.send(control).is_ok() => String::new(),
+                    _ => format!("connection {} is not driven from the console", index),
+                },
+                Err(err) => err,
+            },
+            Command::Shutdown => {
+                self.admin.shutdown_notify.notify_waiters();
+                "shutting down".to_string()
+            }
+            Command::Help => HELP.to_string(),
+        }
+    }
+
+    fn list(&self) -> String {
+        let entries = self.admin.registry.entries(None);
+        if entries.is_empty() {
+            return "no live connections".to_string();
+        }
+
+        entries
+            .iter()
+            .map(|(index, entry)| {
+                let marker = if Some(*index) == self.current {
+                    "*"
+                } else {
+                    " "
+                };
+                let peer = entry.peer.map(|peer| peer.to_string()).unwrap_or_default();
+                let step = entry.progress.step.as_deref().unwrap_or_default();
+                format!("{} #{} {} {}", marker, index, peer, step)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    async fn send(&self, data: &[u8]) -> String {
+        match self.target() {
+            Ok((index, entry)) => match inject(&entry, data).await {
+                Ok(()) => format!("[#{}] sent {} bytes", index, data.len()),
+                Err(err) => format!("[#{}] could not send: {}", index, err),
+            },
+            Err(err) => err,
+        }
+    }
+
+    /// The connection selected, or else the last one accepted.
+    fn target(&self) -> Result<(usize, Entry), String> {
+        match self.current {
+            Some(index) => self
+                .admin
+                .registry
+                .entry(index)
+                .map(|entry| (index, entry))
+                .ok_or_else(|| format!("connection {} is gone", index)),
+            None => self
+                .admin
+                .registry
+                .entries(None)
+                .pop()
+                .ok_or_else(|| "no live connections".to_string()),
+        }
+    }
+}
+
+/// Parses a line typed in the console, `None` if blank.
+fn parse(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+    let (name, arg) = line.split_once(' ').unwrap_or((line, ""));
+    let arg = arg.trim();
+
+    let command = match (name, arg) {
+        ("", _) => return Ok(None),
+        ("list", "") => Command::List,
+        ("use", index) => Command::Use(
+            index
+                .parse()
+                .map_err(|_| format!("invalid connection '{}'", index))?,
+        ),
+        ("send", message) if !message.is_empty() => Command::Send(message.to_string()),
+        ("hex", bytes) => Command::Hex(from_hex(bytes).ok_or("invalid hex")?),
+        ("close", "") => Command::Control(Control::Close),
+        ("reset", "") => Command::Control(Control::Reset),
+        ("scenario", "") => Command::Control(Control::Scenario(None)),
+        ("scenario", scenario) => Command::Control(Control::Scenario(Some(scenario.to_string()))),
+        ("shutdown", "") => Command::Shutdown,
+        ("help", "") => Command::Help,
+        _ => return Err(format!("invalid command '{}', try help", line)),
+    };
+
+    Ok(Some(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{sleep, timeout, Duration};
+
+    use super::*;
+    use crate::server::{ServerConfig, TcpServer};
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(parse("use 2"), Ok(Some(Command::Use(2))));
+        assert_eq!(
+            parse("send logon "),
+            Ok(Some(Command::Send("logon".to_string())))
+        );
+        assert_eq!(parse("hex 48 69"), Ok(Some(Command::Hex(b"Hi".to_vec()))));
+        assert_eq!(
+            parse("scenario"),
+            Ok(Some(Command::Control(Control::Scenario(None))))
+        );
+        assert!(parse("hex 4").is_err());
+        assert!(parse("send").is_err());
+        assert!(parse("close now").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_console_drives_a_connection() {
+        let mapping_file = NamedTempFile::new().unwrap();
+        std::fs::write(
+            mapping_file.path(),
+            r#"
+            name: console
+            messages:
+                hello: "Hello"
+            actions:
+                - message: hello
+                  execute: Recv
+            scenarios:
+                greeter:
+                    actions:
+                        - message: hello
+                          execute: Send
+            "#,
+        )
+        .unwrap();
+        let report_file = NamedTempFile::new().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = ServerConfig {
+            mapping_file_path: mapping_file.path().to_string_lossy().to_string(),
+            report_path: report_file.path().to_string_lossy().to_string(),
+            interactive: true,
+            grace_period: Duration::from_millis(200),
+            ..ServerConfig::default()
+        };
+        let mut server = TcpServer::new(listener, config).unwrap();
+        let mut console = Console {
+            admin: server.admin(),
+            current: None,
+        };
+        let mut events = console.admin.registry.console_events();
+        tokio::spawn(async move { server.run().await });
+
+        assert_eq!(console.execute("send hello").await, "no live connections");
+        let mut first = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut second = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut third = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(console.execute("use 1").await, "using connection 1");
+        assert_eq!(console.execute("send hello").await, "[#1] sent 5 bytes");
+        assert_eq!(console.execute("hex 21").await, "[#1] sent 1 bytes");
+        let mut received = vec![0u8; 6];
+        first.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"Hello!");
+
+        first.write_all(b"Hi").await.unwrap();
+        let output = loop {
+            let (index, event) = events.recv().await.unwrap();
+            if let (1, ConsoleEvent::Received(_)) = (index, &event) {
+                break show(index, &event);
+            }
+        };
+        assert!(output.starts_with("[#1] recv 2 bytes\n00000000  48 69"));
+
+        console.execute("close").await;
+        let mut rest = vec![];
+        timeout(Duration::from_secs(1), first.read_to_end(&mut rest))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rest.is_empty());
+
+        assert_eq!(console.execute("use 2").await, "using connection 2");
+        second.write_all(b"ping").await.unwrap();
+        console.execute("scenario greeter").await;
+        let mut received = vec![0u8; 5];
+        second.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"Hello");
+
+        assert_eq!(console.execute("use 3").await, "using connection 3");
+        console.execute("reset").await;
+        let read = timeout(Duration::from_secs(1), third.read(&mut received))
+            .await
+            .unwrap();
+        assert_eq!(
+            read.unwrap_err().kind(),
+            std::io::ErrorKind::ConnectionReset
+        );
+    }
+}
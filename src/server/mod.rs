@@ -1,7 +1,16 @@
+mod admin;
+mod console;
+mod ready;
+mod reload;
 mod tcp;
 
 use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::sync::Notify;
+use tokio::time::Duration;
+
+use crate::capture::Capture;
+use crate::reporter::ReportFormat;
 
 pub use std::path::Path;
 pub use tcp::run_tcp_server;
@@ -12,5 +21,42 @@ pub use tcp::TcpServer;
 pub struct ServerConfig {
     pub mapping_file_path: String,
     pub report_path: String,
+    pub report_formats: Vec<ReportFormat>,
     pub shutdown_notify: Arc<Notify>,
+    pub grace_period: Duration,
+    pub fail_fast: bool,
+    pub capture: Option<Arc<Capture>>,
+    pub transcript_dir: Option<String>,
+    /// Reload the mapping whenever its file changes, besides on SIGHUP
+    pub watch_mapping: bool,
+    /// Drive connections from a console on stdin, instead of their scenario
+    pub interactive: bool,
+    /// Listener of the HTTP admin API, if enabled
+    pub admin_listener: Option<TcpListener>,
+    /// File to write the address of the server to as JSON, once it accepts
+    /// connections, removed on shutdown
+    pub ready_file: Option<String>,
+    /// Print a `READY <address>` line on stdout, once it accepts connections
+    pub ready_line: bool,
+}
+
+impl Default for ServerConfig {
+    /// Defaults of the command line, without any mapping file
+    fn default() -> ServerConfig {
+        ServerConfig {
+            mapping_file_path: String::new(),
+            report_path: "result.xml".to_string(),
+            report_formats: vec![ReportFormat::Junit],
+            shutdown_notify: Arc::new(Notify::new()),
+            grace_period: Duration::from_secs(1),
+            fail_fast: false,
+            capture: None,
+            transcript_dir: None,
+            watch_mapping: false,
+            interactive: false,
+            admin_listener: None,
+            ready_file: None,
+            ready_line: false,
+        }
+    }
 }
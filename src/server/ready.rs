@@ -0,0 +1,58 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+
+/// Where a running server can be reached, signalled once it accepts connections
+#[derive(Debug, Serialize)]
+pub(crate) struct Ready {
+    pub address: SocketAddr,
+    pub admin_address: Option<SocketAddr>,
+    pub pid: u32,
+}
+
+impl Ready {
+    /// Writes `self` as JSON to `path`, atomically, so a harness polling for
+    /// the file never reads it half written.
+    pub(crate) fn write(&self, path: &str) -> io::Result<()> {
+        let tmp_path = format!("{}.{}.tmp", path, self.pid);
+        let mut tmp_file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut tmp_file, self)?;
+        tmp_file.write_all(b"\n")?;
+        tmp_file.sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Line printed on stdout, for harnesses reading the output of the server.
+    pub(crate) fn line(&self) -> String {
+        format!("READY {}", self.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_file_is_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready.json");
+        let ready = Ready {
+            address: "127.0.0.1:40123".parse().unwrap(),
+            admin_address: None,
+            pid: 42,
+        };
+
+        ready.write(&path.to_string_lossy()).unwrap();
+
+        let content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            content,
+            serde_json::json!({"address": "127.0.0.1:40123", "admin_address": null, "pid": 42})
+        );
+        assert_eq!(ready.line(), "READY 127.0.0.1:40123");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
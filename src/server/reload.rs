@@ -0,0 +1,165 @@
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+
+use crate::mapping::{MappingGuard, MappingState};
+
+/// Time given to an editor to finish writing the file before reloading it
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// Reloads the mapping from `path` on SIGHUP, and also whenever the file changes
+/// if `watch`, until the task running it is aborted.
+///
+/// New connections get the reloaded mapping, live ones keep theirs. A mapping
+/// that fails to load or validate is rejected, keeping the previous one.
+pub(crate) async fn reload_mapping(guard: Arc<MappingGuard>, path: String, watch: bool) {
+    let mut hangup = Hangup::new();
+    let mut changes = match watch {
+        true => Changes::watch(&path),
+        false => Changes::none(),
+    };
+    let mut last_version = version(&path);
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("SIGHUP received, reloading the mapping"),
+            _ = changes.recv() => {
+                // Editors may write the file in several steps, or touch it only
+                time::sleep(SETTLE_TIME).await;
+                changes.drain();
+                if version(&path) == last_version {
+                    continue;
+                }
+                info!("mapping file changed, reloading it");
+            }
+        }
+        last_version = version(&path);
+
+        let reloaded = MappingState::from_file(path.clone()).and_then(|state| {
+            let name = state.mapping_name.clone();
+            guard.replace(state).map(|()| name)
+        });
+        match reloaded {
+            Ok(name) => info!("mapping '{}' reloaded for new connections", name),
+            Err(err) => error!("mapping not reloaded, keeping the previous one: {:#}", err),
+        }
+    }
+}
+
+/// Hash of the content of a file, telling when it changed
+fn version(path: &str) -> Option<u64> {
+    let content = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+
+    Some(hasher.finish())
+}
+
+/// Stream of changes to a file, notified by the file system
+struct Changes {
+    /// Watches the directory of the file, as editors often replace it
+    _watcher: Option<RecommendedWatcher>,
+    events: mpsc::UnboundedReceiver<()>,
+}
+
+impl Changes {
+    fn watch(path: &str) -> Changes {
+        let (tx, events) = mpsc::unbounded_channel();
+        let file = Path::new(path);
+        let name = file.file_name().map(|name| name.to_os_string());
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if event.kind.is_access() => {}
+                Ok(event) => {
+                    if event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == name.as_deref())
+                    {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(err) => error!("watching the mapping file failed: {:}", err),
+            })
+            .and_then(|mut watcher| {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                Ok(watcher)
+            });
+
+        match watcher {
+            Ok(watcher) => Changes {
+                _watcher: Some(watcher),
+                events,
+            },
+            Err(err) => {
+                error!("could not watch {}, reload it with SIGHUP: {:}", path, err);
+                Changes::none()
+            }
+        }
+    }
+
+    /// No changes, ever
+    fn none() -> Changes {
+        let (_, events) = mpsc::unbounded_channel();
+        Changes {
+            _watcher: None,
+            events,
+        }
+    }
+
+    async fn recv(&mut self) {
+        if self.events.recv().await.is_none() {
+            std::future::pending::<()>().await
+        }
+    }
+
+    /// Forgets the changes notified so far.
+    fn drain(&mut self) {
+        while self.events.try_recv().is_ok() {}
+    }
+}
+
+/// Stream of SIGHUP signals, never raised where there are none
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Hangup {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let signal = signal(SignalKind::hangup())
+                .inspect_err(|err| error!("could not listen to SIGHUP: {:}", err))
+                .ok();
+            Hangup { signal }
+        }
+        #[cfg(not(unix))]
+        Hangup {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+
+        std::future::pending::<()>().await
+    }
+}
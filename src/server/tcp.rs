@@ -1,60 +1,205 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 
-use log::{debug, error, info};
+use socket2::SockRef;
 use tokio::{
     net::{TcpListener, TcpStream},
-    sync::Semaphore,
-    time::{self, Duration},
+    sync::{mpsc, watch, Semaphore},
+    task::JoinHandle,
+    time::{self, Duration, Instant},
 };
+use tracing::{debug, error, field, info, info_span, Instrument};
 
-use crate::{connection::ConnHandler, mapping::MappingGuard};
+use crate::{
+    connection::ConnHandler,
+    mapping::MappingGuard,
+    registry::Registry,
+    reporter::{Reports, RunReport},
+    shutdown::Shutdown,
+};
 
+use super::admin::{self, Admin};
+use super::console;
+use super::ready::Ready;
+use super::reload;
 use super::ServerConfig;
 
 const MAX_CONNECTIONS: usize = 10;
 
 #[derive(Debug)]
 pub struct TcpServer {
-    mapping_guard: MappingGuard,
+    mapping_guard: Arc<MappingGuard>,
     listener: TcpListener,
     limit_conns: Arc<Semaphore>,
-    config: ServerConfig,
+    registry: Arc<Registry>,
+    config: Arc<ServerConfig>,
+
+    /// Reports of all finished connections
+    reports: Arc<Mutex<Reports>>,
+
+    /// Sends the deadline of the grace period to connections, once shutting down
+    notify_shutdown: watch::Sender<Option<Instant>>,
+
+    /// Cloned into each connection, so the server knows when all of them are done
+    shutdown_complete_tx: mpsc::Sender<()>,
+    shutdown_complete_rx: mpsc::Receiver<()>,
 }
 
 impl TcpServer {
-    pub fn new(listener: TcpListener, config: ServerConfig) -> TcpServer {
-        TcpServer {
-            mapping_guard: MappingGuard::new(config.mapping_file_path.clone()),
+    /// Loads the mapping of the server, failing if it is invalid.
+    pub fn new(listener: TcpListener, config: ServerConfig) -> crate::Result<TcpServer> {
+        let mapping_guard = MappingGuard::new(config.mapping_file_path.clone())?;
+
+        // Accepted sockets inherit the receive buffer size from the listener
+        if let Some(size) = mapping_guard
+            .mapping()
+            .state
+            .try_read()
+            .unwrap()
+            .receive_buffer
+        {
+            if let Err(err) = SockRef::from(&listener).set_recv_buffer_size(size) {
+                error!("could not set receive buffer size: {:}", err);
+            }
+        }
+
+        let mut reports = Reports::new(&config.report_path, &config.report_formats);
+        if let Some(dir) = &config.transcript_dir {
+            reports.set_transcript_dir(dir);
+        }
+        // Until a connection finishes, so no stale report is left if none does
+        if let Err(err) = reports.write_empty() {
+            error!("could not write empty report: {:}", err);
+        }
+
+        let (notify_shutdown, _) = watch::channel(None);
+        let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+
+        Ok(TcpServer {
+            mapping_guard: Arc::new(mapping_guard),
             listener,
             limit_conns: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
-            config,
-        }
+            registry: Arc::new(Registry::default()),
+            reports: Arc::new(Mutex::new(reports)),
+            config: Arc::new(config),
+            notify_shutdown,
+            shutdown_complete_tx,
+            shutdown_complete_rx,
+        })
     }
 
     pub async fn run(&mut self) -> crate::Result<()> {
         info!("accepting connections");
 
+        let mut conn_index = 0;
+
         loop {
             let permit = self.limit_conns.clone().acquire_owned().await.unwrap();
             let socket = self.accept().await?;
-            let loop_notify = self.config.shutdown_notify.clone();
+            conn_index += 1;
 
-            let mut handler = ConnHandler::new(
-                self.mapping_guard.mapping(),
-                socket,
-                self.config.report_path.clone(),
-            );
+            let peer = socket
+                .peer_addr()
+                .map(|peer| peer.to_string())
+                .unwrap_or_default();
+            let span = info_span!("conn", index = conn_index, %peer, scenario = field::Empty);
+
+            let mut handler = span.in_scope(|| {
+                ConnHandler::new(
+                    self.mapping_guard.mapping(),
+                    socket,
+                    conn_index,
+                    self.registry.clone(),
+                    Shutdown::new(
+                        self.notify_shutdown.subscribe(),
+                        self.shutdown_complete_tx.clone(),
+                    ),
+                    self.config.clone(),
+                )
+            });
+            let registry = self.registry.clone();
+            let reports = self.reports.clone();
+
+            tokio::spawn(
+                async move {
+                    if let Err(err) = handler.run().await {
+                        error!("error: {:}", err);
+                    }
+
+                    let added = reports.lock().unwrap().add(conn_index, handler.reporter());
+                    if let Err(err) = added {
+                        error!(
+                            "writing report of connection {:} failed: {:}",
+                            conn_index, err
+                        );
+                    }
+                    registry.unregister(conn_index);
 
-            tokio::spawn(async move {
-                if let Err(err) = handler.run(loop_notify).await {
-                    error!("error: {:}", err);
+                    drop(permit);
                 }
+                .instrument(span),
+            );
+        }
+    }
 
-                drop(permit);
-            });
+    /// Tells harnesses waiting on the server where it can be reached.
+    fn signal_ready(&self, ready: Ready) {
+        info!("ready on {}", ready.address);
+        if let Some(path) = &self.config.ready_file {
+            if let Err(err) = ready.write(path) {
+                error!("could not write ready file {}: {:}", path, err);
+            }
+        }
+        if self.config.ready_line {
+            println!("{}", ready.line());
         }
     }
 
+    /// Reloads the mapping of new connections on SIGHUP, or also when its file
+    /// changes if watching it.
+    fn reload_mapping(&self) -> JoinHandle<()> {
+        tokio::spawn(reload::reload_mapping(
+            self.mapping_guard.clone(),
+            self.config.mapping_file_path.clone(),
+            self.config.watch_mapping,
+        ))
+    }
+
+    /// Handle on the state of this server, for the admin API.
+    pub(crate) fn admin(&self) -> Admin {
+        Admin {
+            mapping_guard: self.mapping_guard.clone(),
+            registry: self.registry.clone(),
+            reports: self.reports.clone(),
+            shutdown_notify: self.config.shutdown_notify.clone(),
+        }
+    }
+
+    /// Gives the connections a grace period to finish, waiting for all of them
+    /// to write their reports, and returns their results.
+    pub async fn shutdown(self) -> RunReport {
+        let TcpServer {
+            config,
+            reports,
+            notify_shutdown,
+            shutdown_complete_tx,
+            mut shutdown_complete_rx,
+            ..
+        } = self;
+
+        info!(
+            "waiting up to {:?} for connections to finish",
+            config.grace_period
+        );
+        notify_shutdown.send_replace(Some(Instant::now() + config.grace_period));
+
+        drop(shutdown_complete_tx);
+        let _ = shutdown_complete_rx.recv().await;
+
+        let report = reports.lock().unwrap().run_report();
+        report
+    }
+
     async fn accept(&mut self) -> crate::Result<TcpStream> {
         let mut backoff = 1;
 
@@ -78,10 +223,66 @@ impl TcpServer {
 }
 
 /// Entry point for running the TCP server.
-pub async fn run_tcp_server(listener: TcpListener, config: ServerConfig) {
-    let mut server = TcpServer::new(listener, config);
+///
+/// Runs until `shutdown` completes or a `Shutdown` action is executed, then
+/// stops accepting connections and shuts down gracefully, returning the results
+/// of all connections. The admin API, if enabled, is served
+/// until then. Fails only if the server can not start, e.g. on an invalid mapping.
+pub async fn run_tcp_server(
+    listener: TcpListener,
+    mut config: ServerConfig,
+    shutdown: impl Future,
+) -> crate::Result<RunReport> {
+    let shutdown_notify = config.shutdown_notify.clone();
+    let admin_listener = config.admin_listener.take();
+    let address = listener.local_addr();
+    let admin_address = admin_listener
+        .as_ref()
+        .and_then(|listener| listener.local_addr().ok());
+    let mut server = TcpServer::new(listener, config)?;
+    let reload = server.reload_mapping();
+    let console = server.config.interactive.then(|| {
+        let events = server.registry.console_events();
+        tokio::spawn(console::run(server.admin(), events))
+    });
+
+    let admin = admin_listener.map(|listener| {
+        info!("serving admin API on {:?}", listener.local_addr());
+        tokio::spawn(admin::serve(listener, server.admin()))
+    });
+
+    // The mapping is loaded by now, so clients connecting get served
+    match address {
+        Ok(address) => server.signal_ready(Ready {
+            address,
+            admin_address,
+            pid: std::process::id(),
+        }),
+        Err(err) => error!("could not get the server address: {:}", err),
+    }
+
+    tokio::select! {
+        res = server.run() => {
+            if let Err(err) = res {
+                error!("{}", err);
+            }
+        }
+        _ = shutdown => { info!("server interrupted") }
+        _ = shutdown_notify.notified() => { info!("server shutdown called") }
+    }
 
-    if let Err(err) = server.run().await {
-        error!("{}", err);
+    let ready_file = server.config.ready_file.clone();
+    let report = server.shutdown().await;
+    if let Some(path) = ready_file {
+        let _ = std::fs::remove_file(path);
     }
+    reload.abort();
+    if let Some(console) = console {
+        console.abort();
+    }
+    if let Some(admin) = admin {
+        admin.abort();
+    }
+
+    Ok(report)
 }
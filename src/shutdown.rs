@@ -0,0 +1,70 @@
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Instant};
+
+/// Listens for the server shutdown, on behalf of a connection.
+///
+/// Connections get a grace period to finish after the shutdown starts. Once all
+/// listeners are dropped, the server knows all connections are done.
+#[derive(Debug, Clone)]
+pub(crate) struct Shutdown {
+    /// Deadline of the grace period, set once the shutdown starts
+    deadline: watch::Receiver<Option<Instant>>,
+
+    /// Dropped along with the connection, signalling it is done
+    _complete: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(
+        deadline: watch::Receiver<Option<Instant>>,
+        complete: mpsc::Sender<()>,
+    ) -> Shutdown {
+        Shutdown {
+            deadline,
+            _complete: complete,
+        }
+    }
+
+    /// Waits until the grace period after the server shutdown elapses.
+    pub(crate) async fn expired(&mut self) {
+        let deadline = self
+            .deadline
+            .wait_for(Option::is_some)
+            .await
+            .map(|deadline| deadline.unwrap());
+
+        match deadline {
+            Ok(deadline) => sleep_until(deadline).await,
+            // The server is gone without shutting down, so it never will
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{timeout, Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_expired_waits_for_grace_period_after_shutdown() {
+        let (deadline_tx, deadline_rx) = watch::channel(None);
+        let (complete_tx, mut complete_rx) = mpsc::channel(1);
+        let mut shutdown = Shutdown::new(deadline_rx, complete_tx);
+
+        assert!(timeout(Duration::from_millis(50), shutdown.expired())
+            .await
+            .is_err());
+
+        let grace = Duration::from_millis(100);
+        deadline_tx.send(Some(Instant::now() + grace)).unwrap();
+        let start = Instant::now();
+        shutdown.expired().await;
+
+        assert!(start.elapsed() >= grace - Duration::from_millis(10));
+
+        drop(shutdown);
+        assert!(complete_rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Named signals and barriers shared by all connections, for coordinating
+/// their actions
+#[derive(Debug, Default)]
+pub(crate) struct Signals {
+    /// Times each signal was raised
+    raised: Mutex<HashMap<String, usize>>,
+    /// Connections that arrived at each barrier, separate from the signals
+    arrived: Mutex<HashMap<String, usize>>,
+    notify: Notify,
+}
+
+/// Arrival of a connection at a barrier, withdrawn if it gives up waiting
+/// before all parties of its generation arrived
+struct Arrival<'a> {
+    signals: &'a Signals,
+    name: &'a str,
+    /// Arrivals passing the barrier, the end of the generation
+    target: usize,
+    passed: bool,
+}
+
+impl Signals {
+    /// Raises the signal `name`, waking up connections waiting for it.
+    pub(crate) fn raise(&self, name: &str) {
+        *self
+            .raised
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += 1;
+
+        self.notify.notify_waiters();
+    }
+
+    /// Waits until the signal `name` was raised at least `times`.
+    pub(crate) async fn wait(&self, name: &str, times: usize) {
+        loop {
+            // Registered before checking, so no raise is missed in between
+            let notified = self.notify.notified();
+
+            if self.count(name) >= times {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Arrives at the barrier `name`, waiting until `parties` connections
+    /// arrived at it, this one included.
+    ///
+    /// Arrivals are counted in generations of `parties`, so a barrier can be
+    /// reused, each time waiting for as many new arrivals.
+    pub(crate) async fn barrier(&self, name: &str, parties: usize) {
+        let target = {
+            let mut arrived = self.arrived.lock().unwrap();
+            let count = arrived.entry(name.to_string()).or_default();
+            *count += 1;
+            count.div_ceil(parties) * parties
+        };
+        self.notify.notify_waiters();
+
+        let mut arrival = Arrival {
+            signals: self,
+            name,
+            target,
+            passed: false,
+        };
+        loop {
+            let notified = self.notify.notified();
+
+            if self.arrivals(name) >= target {
+                arrival.passed = true;
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Forgets all signals raised and barriers arrived at so far.
+    pub(crate) fn reset(&self) {
+        self.raised.lock().unwrap().clear();
+        self.arrived.lock().unwrap().clear();
+    }
+
+    /// Times the signal `name` was raised.
+    pub(crate) fn count(&self, name: &str) -> usize {
+        self.raised
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Times a connection arrived at the barrier `name`, minus the ones that
+    /// gave up.
+    pub(crate) fn arrivals(&self, name: &str) -> usize {
+        self.arrived
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Arrival<'_> {
+    fn drop(&mut self) {
+        if self.passed {
+            return;
+        }
+
+        let mut arrived = self.signals.arrived.lock().unwrap();
+        if let Some(count) = arrived.get_mut(self.name) {
+            if *count < self.target {
+                *count -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::time::{sleep, timeout, Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_returns_once_signal_is_raised() {
+        let signals = Arc::new(Signals::default());
+        let waiting = signals.clone();
+
+        let waiter = tokio::spawn(async move { waiting.wait("subscribed", 2).await });
+
+        signals.raise("subscribed");
+        assert!(!waiter.is_finished());
+
+        signals.raise("subscribed");
+        assert!(timeout(Duration::from_secs(1), waiter).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_barrier_is_reusable_and_apart_from_signals() {
+        let signals = Arc::new(Signals::default());
+        let short = Duration::from_millis(50);
+
+        for _ in 0..2 {
+            let waiting = signals.clone();
+            let first = tokio::spawn(async move { waiting.barrier("ready", 2).await });
+
+            signals.raise("ready");
+            assert!(timeout(short, signals.wait("ready", 1)).await.is_ok());
+            sleep(short).await;
+            assert!(!first.is_finished());
+
+            assert!(timeout(short, signals.barrier("ready", 2)).await.is_ok());
+            assert!(timeout(Duration::from_secs(1), first).await.is_ok());
+        }
+        assert_eq!(signals.arrivals("ready"), 4);
+
+        // A party giving up is withdrawn, not passing the next one alone
+        assert!(timeout(short, signals.barrier("ready", 2)).await.is_err());
+        assert_eq!(signals.arrivals("ready"), 4);
+        assert!(timeout(short, signals.barrier("ready", 2)).await.is_err());
+    }
+}
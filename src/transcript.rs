@@ -0,0 +1,120 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use crate::hexdump::hexdump;
+
+/// Transcript of a connection, shared by it and its reporter
+pub(crate) type SharedTranscript = Arc<Mutex<Transcript>>;
+
+/// Timestamped record of every read and write on a connection, along with
+/// the results of the steps they made
+#[derive(Debug)]
+pub struct Transcript {
+    start: Instant,
+    entries: Vec<(Duration, Entry)>,
+}
+
+#[derive(Debug)]
+enum Entry {
+    Read(Vec<u8>),
+    Write(Vec<u8>),
+    Note(String),
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript {
+            start: Instant::now(),
+            entries: vec![],
+        }
+    }
+
+    /// Records data read from the peer.
+    pub fn read(&mut self, data: &[u8]) {
+        self.push(Entry::Read(data.to_vec()));
+    }
+
+    /// Records data written to the peer.
+    pub fn write(&mut self, data: &[u8]) {
+        self.push(Entry::Write(data.to_vec()));
+    }
+
+    /// Records a note, like the result of a step.
+    pub fn note(&mut self, note: String) {
+        self.push(Entry::Note(note));
+    }
+
+    /// Renders the transcript, each entry timestamped from the connection start.
+    pub fn render(&self) -> String {
+        let mut rendered = String::new();
+
+        for (at, entry) in &self.entries {
+            let _ = write!(rendered, "[{:>12.6}] ", at.as_secs_f64());
+            let _ = match entry {
+                Entry::Read(data) => write!(
+                    rendered,
+                    "recv {} bytes\n{}",
+                    data.len(),
+                    indent(&hexdump(data))
+                ),
+                Entry::Write(data) => write!(
+                    rendered,
+                    "send {} bytes\n{}",
+                    data.len(),
+                    indent(&hexdump(data))
+                ),
+                Entry::Note(note) => writeln!(rendered, "{}", note),
+            };
+        }
+
+        rendered
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.push((self.start.elapsed(), entry));
+    }
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Transcript::new()
+    }
+}
+
+/// Indents the lines of a hexdump under the timestamp of its entry
+fn indent(dump: &str) -> String {
+    dump.lines()
+        .map(|line| format!("{:16}{}\n", "", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_lists_entries_with_hexdump() {
+        let mut transcript = Transcript::new();
+        transcript.read(b"Hello, world");
+        transcript.note("step 'msg1': passed".to_string());
+        transcript.write(b"!");
+
+        let rendered = transcript.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("[    0.0"));
+        assert!(lines[0].ends_with("] recv 12 bytes"));
+        assert_eq!(
+            lines[1],
+            "                00000000  48 65 6c 6c 6f 2c 20 77 |Hello, w|"
+        );
+        assert_eq!(
+            lines[2],
+            "                00000008  6f 72 6c 64             |orld    |"
+        );
+        assert!(lines[3].ends_with("] step 'msg1': passed"));
+        assert!(lines[4].ends_with("] send 1 bytes"));
+    }
+}
@@ -2,27 +2,284 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use claims::assert_ok;
-use env_logger::{Builder, Env};
-use log::info;
+use mocktide::journal::FrameStatus;
+use mocktide::reporter::{Outcome, RunReport};
 use mocktide::server::{run_tcp_server, ServerConfig};
 use tempfile::NamedTempFile;
-use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::info;
 
-fn create_mapping_file() -> NamedTempFile {
-    use std::io::Write;
-    let mapping: &str = r#"
-        name: hello
+static HELLO_MAPPING: &str = r#"
+    name: hello
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+
+    actions:
+        - message: msg1
+          execute: Recv
+        - execute: Shutdown
+"#;
+
+static CHUNKED_MAPPING: &str = r#"
+    name: chunked
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+        msg2: "\x21"
+
+    actions:
+        - message: msg1
+          execute: Send
+          chunk_size: 2
+          chunk_delay_ms: 200
+          coalesce: [msg2]
+        - execute: Shutdown
+"#;
+
+static IMPAIRED_MAPPING: &str = r#"
+    name: impaired
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+
+    impairment:
+        latency_ms: 300
+        bandwidth: 20
+
+    actions:
+        - message: msg1
+          execute: Send
+        - execute: Shutdown
+"#;
+
+static SWAPPED_MAPPING: &str = r#"
+    name: swapped
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+        msg2: "\x21"
+
+    actions:
+        - message: msg1
+          execute: Send
+          corrupt:
+              truncate: 4
+              swap_next: true
+        - message: msg2
+          execute: Send
+        - execute: Shutdown
+"#;
+
+static EXPECTATIONS_MAPPING: &str = r#"
+    name: expectations
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+
+    actions:
+        - execute: ExpectSilence
+          duration_ms: 200
+        - execute: ExpectClose
+          duration_ms: 50
+        - message: msg1
+          execute: Recv
+        - execute: ExpectSilence
+          duration_ms: 100
+        - execute: ExpectClose
+          duration_ms: 1000
+        - execute: Shutdown
+"#;
+
+static SCRIPT_MAPPING: &str = r#"
+    name: script
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+        msg2: "\x21"
+
+    actions:
+        - message: msg1
+          execute: Send
+        - execute: PauseRead
+          duration_ms: 100
+        - message: msg2
+          execute: Recv
+        - message: msg1
+          execute: Send
+        - execute: Shutdown
+"#;
+
+static ADMIN_MAPPING: &str = r#"
+    name: admin
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+        msg2: "\x21"
+
+    actions:
+        - message: msg1
+          execute: Send
+        - message: msg2
+          execute: Recv
+"#;
+
+static RELOADED_MAPPING: &str = r#"
+    name: reloaded
+
+    messages:
+        msg1: "\x48\x65\x6C\x6C\x6F"
+
+    actions:
+        - message: msg1
+          execute: Recv
+"#;
+
+static STUB_MAPPING: &str = r#"
+    name: stub
+    mode: stub
+
+    messages:
+        ping: "ping"
+        pong: "pong"
+        nack: "nack"
+
+    rules:
+        - when: ping
+          reply: [pong, pong]
+
+    default:
+        reply: [nack]
+"#;
+
+static STATES_MAPPING: &str = r#"
+    name: states
+    mode: states
+    initial: logon
+
+    messages:
+        logon: "logon"
+        ack: "ack"
+        ping: "ping"
+        pong: "pong"
+        bye: "bye"
+
+    states:
+        logon:
+            rules:
+                - when: logon
+                  reply: [ack]
+                  goto: trading
+        trading:
+            rules:
+                - when: ping
+                  reply: [pong]
+            timeout_ms: 300
+            next: logout
+        logout:
+            actions:
+                - message: bye
+                  execute: Send
+                - execute: ExpectClose
+                  duration_ms: 1000
+"#;
+
+static SCENARIOS_MAPPING: &str = r#"
+    name: scenarios
+
+    messages:
+        one: "one"
+        two: "two"
+        hey: "hey"
+        ho: "ho"
+
+    scenarios:
+        primary:
+            actions:
+                - message: one
+                  execute: Send
+        secondary:
+            actions:
+                - message: two
+                  execute: Send
+        greeter:
+            mode: stub
+            rules:
+                - when: hey
+                  reply: [ho]
+
+    select:
+        - connection: 1
+          scenario: primary
+        - connection: 2
+          scenario: secondary
+        - first_message: hey
+          scenario: greeter
+"#;
+
+static SIGNALS_MAPPING: &str = r#"
+    name: signals
+
+    messages:
+        sub: "sub"
+        snap: "snap"
 
-        messages:
-            msg1: "\x48\x65\x6C\x6C\x6F"
+    scenarios:
+        control:
+            actions:
+                - message: sub
+                  execute: Recv
+                - execute: Signal
+                  signal: subscribed
+        data:
+            actions:
+                - execute: WaitFor
+                  signal: subscribed
+                  duration_ms: 2000
+                - message: snap
+                  execute: Send
 
-        actions:
-            - message: msg1
-              execute: Recv
-            - execute: Shutdown
-    "#;
+    select:
+        - connection: 1
+          scenario: control
+        - connection: 2
+          scenario: data
+"#;
+
+static BROADCAST_MAPPING: &str = r#"
+    name: broadcast
+
+    messages:
+        go: "go"
+        tick: "tick"
+
+    scenarios:
+        subscriber:
+            group: feed
+            actions:
+                - execute: ExpectClose
+                  duration_ms: 2000
+        publisher:
+            actions:
+                - message: go
+                  execute: Recv
+                - message: tick
+                  execute: Broadcast
+                  group: feed
+
+    select:
+        - connection: 3
+          scenario: publisher
+        - peer_ip: 127.0.0.1
+          scenario: subscriber
+"#;
+
+fn create_mapping_file(mapping: &str) -> NamedTempFile {
+    use std::io::Write;
 
     let mut tmpfile = tempfile::NamedTempFile::new().unwrap();
     write!(tmpfile, "{}", mapping).unwrap();
@@ -30,35 +287,109 @@ fn create_mapping_file() -> NamedTempFile {
     tmpfile
 }
 
+/// Config of a server for the given files, changed by each test as needed
+fn test_config(mapping_file: &NamedTempFile, report_file: &NamedTempFile) -> ServerConfig {
+    ServerConfig {
+        mapping_file_path: mapping_file.path().to_string_lossy().to_string(),
+        report_path: report_file.path().to_string_lossy().to_string(),
+        grace_period: Duration::from_millis(200),
+        ..ServerConfig::default()
+    }
+}
+
 struct TestServer {
     port: u16,
     shutdown: Arc<Notify>,
+    report_file: NamedTempFile,
+    mapping_file: NamedTempFile,
+    server: JoinHandle<RunReport>,
+}
+
+impl TestServer {
+    /// Waits for the report of a connection to be written and returns its content
+    async fn report(&self) -> String {
+        for _ in 0..50 {
+            let content = std::fs::read_to_string(self.report_file.path()).unwrap();
+            if content.contains("<testsuite ") {
+                return content;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        panic!("report was not written");
+    }
 }
 
 /// Spawn the server and returns the shutdown notifier for it
-async fn test_server() -> TestServer {
+async fn test_server(mapping: &str) -> TestServer {
+    test_server_with(mapping, |config| config).await
+}
+
+/// Spawn the server with the test config changed by `configure`, along with
+/// the handle returning its results
+async fn test_server_with(
+    mapping: &str,
+    configure: impl FnOnce(ServerConfig) -> ServerConfig,
+) -> TestServer {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let port = listener.local_addr().unwrap().port();
     let shutdown_notify = Arc::new(Notify::new());
 
     let shutdown = shutdown_notify.clone();
 
-    tokio::spawn(async move {
-        let mapping_file = create_mapping_file();
-        let report_file = tempfile::NamedTempFile::new().unwrap();
+    let mapping_file = create_mapping_file(mapping);
+    let report_file = tempfile::NamedTempFile::new().unwrap();
 
-        let mapping_file_path = mapping_file.path().to_string_lossy().to_string();
-        let report_path = report_file.path().to_string_lossy().to_string();
-
-        let config = ServerConfig {
-            mapping_file_path,
-            report_path,
-            shutdown_notify,
-        };
-        run_tcp_server(listener, config).await
+    let config = configure(ServerConfig {
+        shutdown_notify,
+        grace_period: Duration::from_millis(500),
+        ..test_config(&mapping_file, &report_file)
+    });
+    let server = tokio::spawn(async move {
+        run_tcp_server(listener, config, std::future::pending::<()>())
+            .await
+            .unwrap()
     });
 
-    TestServer { port, shutdown }
+    TestServer {
+        port,
+        shutdown,
+        report_file,
+        mapping_file,
+        server,
+    }
+}
+
+/// Sends a request to the admin API, returning the status code and body
+async fn admin_request(port: u16, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+
+    (status, body)
+}
+
+/// Test client with a small send buffer, so that with a small receive buffer
+/// on the server too, a large message can not be buffered whole
+async fn connect_with_small_send_buffer(server_port: u16) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_send_buffer_size(4096).unwrap();
+    let address = format!("127.0.0.1:{}", server_port).parse().unwrap();
+
+    socket.connect(address).await.unwrap()
 }
 
 /// Test client for writing to server
@@ -73,8 +404,10 @@ async fn write_to_server(server_port: u16, data: &Bytes) -> Result<(), std::io::
 /// Will be improve when output of results is made.
 #[tokio::test]
 async fn test_tcp_server_completes_expected_actions() {
-    let test_server = test_server().await;
-    let _ = Builder::from_env(Env::default().default_filter_or("debug")).try_init();
+    let test_server = test_server(HELLO_MAPPING).await;
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter("debug")
+        .try_init();
     info!("test server port: {}", &test_server.port);
 
     let res = write_to_server(test_server.port, &Bytes::from("\x48\x65\x6C\x6C\x6F")).await;
@@ -82,3 +415,798 @@ async fn test_tcp_server_completes_expected_actions() {
     test_server.shutdown.notified().await;
     assert_ok!(res);
 }
+
+#[tokio::test]
+async fn test_tcp_server_sends_message_in_chunks() {
+    let test_server = test_server(CHUNKED_MAPPING).await;
+    let shutdown = test_server.shutdown.notified();
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut buffer = [0u8; 16];
+    let first_read = stream.read(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..first_read], b"He");
+
+    let mut received = buffer[..first_read].to_vec();
+    while received.len() < 6 {
+        let n = stream.read(&mut buffer).await.unwrap();
+        received.extend_from_slice(&buffer[..n]);
+    }
+    assert_eq!(received, b"Hello!");
+
+    shutdown.await;
+}
+
+#[tokio::test]
+async fn test_tcp_server_applies_impairment_to_sends() {
+    let test_server = test_server(IMPAIRED_MAPPING).await;
+    let shutdown = test_server.shutdown.notified();
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut buffer = [0u8; 16];
+    let first_read = stream.read(&mut buffer).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert_eq!(&buffer[..first_read], b"He");
+
+    shutdown.await;
+}
+
+#[tokio::test]
+async fn test_tcp_server_applies_impairment_to_broadcasts() {
+    let mapping = BROADCAST_MAPPING.replace(
+        "    scenarios:",
+        "    impairment:\n        latency_ms: 300\n\n    scenarios:",
+    );
+    let test_server = test_server(&mapping).await;
+    let address = format!("127.0.0.1:{}", test_server.port);
+
+    let mut subscribers = Vec::new();
+    for _ in 0..2 {
+        subscribers.push(TcpStream::connect(&address).await.unwrap());
+        sleep(Duration::from_millis(50)).await;
+    }
+    let mut publisher = TcpStream::connect(&address).await.unwrap();
+    let start = Instant::now();
+    publisher.write_all(b"go").await.unwrap();
+
+    for subscriber in subscribers.iter_mut() {
+        let mut received = vec![0u8; 4];
+        subscriber.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"tick");
+    }
+    // Subscribers are written concurrently, each delayed once
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_millis(600), "took {:?}", elapsed);
+}
+
+#[tokio::test]
+async fn test_tcp_server_sends_corrupted_messages() {
+    let test_server = test_server(SWAPPED_MAPPING).await;
+    let shutdown = test_server.shutdown.notified();
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut received = vec![0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"!Hell");
+
+    shutdown.await;
+}
+
+#[tokio::test]
+async fn test_tcp_server_skips_swapped_message_never_sent() {
+    let mapping = SWAPPED_MAPPING.replace(
+        "        - message: msg2\n          execute: Send",
+        "        - message: msg2\n          execute: Recv\n        - message: msg2\n          execute: Send",
+    );
+    let test_server = test_server(&mapping).await;
+
+    let stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    drop(stream);
+
+    sleep(Duration::from_millis(100)).await;
+    test_server.shutdown.notify_waiters();
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    let report = std::fs::read_to_string(test_server.report_file.path()).unwrap();
+    assert_eq!(report.matches("<testcase").count(), 4);
+    assert_eq!(report.matches("<skipped/>").count(), 3);
+    assert!(report.contains(r#"<testcase name="msg1" time="0"><skipped/>"#));
+    assert_eq!(run.summary.failures, 1);
+}
+
+#[tokio::test]
+async fn test_tcp_server_reports_silence_and_close_expectations() {
+    let test_server = test_server(EXPECTATIONS_MAPPING).await;
+    let shutdown = test_server.shutdown.notified();
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    sleep(Duration::from_millis(300)).await;
+    stream.write_all(b"Hello").await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    drop(stream);
+
+    shutdown.await;
+    let report = test_server.report().await;
+
+    assert!(report.contains(r#"tests="6""#));
+    assert!(report.contains(r#"failures="1""#));
+    assert!(report.contains("expected Closed, got Silent"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_reports_every_action() {
+    let test_server = test_server(SCRIPT_MAPPING).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut received = vec![0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+    drop(stream);
+
+    let report = test_server.report().await;
+    assert!(report.contains(r#"tests="5""#));
+    assert!(report.contains(r#"failures="1""#));
+    assert_eq!(report.matches(r#"<testcase name="msg1""#).count(), 2);
+    assert!(report.contains(r#"<testcase name="PauseRead" time="0.1"#));
+    assert!(report.contains("connection closed before message was recv"));
+    assert_eq!(report.matches("<skipped/>").count(), 2);
+
+    assert!(report.contains("<system-out><![CDATA["));
+    assert!(report.contains("] send 5 bytes"));
+    assert!(report.contains("00000000  48 65 6c 6c 6f          |Hello   |"));
+    assert!(report.contains("] step 'msg1': passed"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_replies_following_stub_rules() {
+    let test_server = test_server(STUB_MAPPING).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut received = vec![0u8; 8];
+    stream.write_all(b"ping").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"pongpong");
+
+    let mut received = vec![0u8; 4];
+    stream.write_all(b"pong").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"nack");
+    drop(stream);
+
+    let report = test_server.report().await;
+    assert!(report.contains(r#"tests="2""#));
+    assert!(report.contains(r#"<testcase name="default""#));
+    assert!(report.contains(r#"failures="0""#));
+}
+
+#[tokio::test]
+async fn test_tcp_server_reports_frames_no_stub_rule_handles() {
+    let mapping = STUB_MAPPING.replace("    default:\n        reply: [nack]\n", "");
+    let test_server = test_server(&mapping).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    stream.write_all(b"pong").await.unwrap();
+    drop(stream);
+
+    let report = test_server.report().await;
+    assert!(report.contains(r#"no rule matches b&quot;pong&quot;"#));
+    assert!(report.contains(r#"failures="1""#));
+}
+
+#[tokio::test]
+async fn test_tcp_server_stub_resynchronizes_after_junk() {
+    let test_server = test_server(STUB_MAPPING).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut received = vec![0u8; 12];
+    stream.write_all(b"xxping").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"nackpongpong");
+    drop(stream);
+
+    let report = test_server.report().await;
+    assert!(report.contains(r#"tests="2""#));
+    assert!(report.contains(r#"failures="0""#));
+}
+
+#[tokio::test]
+async fn test_tcp_server_moves_between_states() {
+    let test_server = test_server(STATES_MAPPING).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let mut received = vec![0u8; 3];
+    stream.write_all(b"logon").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"ack");
+
+    let mut received = vec![0u8; 4];
+    stream.write_all(b"ping").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"pong");
+
+    let mut received = vec![0u8; 3];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"bye");
+    drop(stream);
+
+    let report = test_server.report().await;
+    assert!(report.contains(r#"tests="4""#));
+    assert!(report.contains(r#"failures="0""#));
+    assert!(report.contains(r#"classname="trading""#));
+    assert!(report.contains("client latency: 1 samples"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_selects_scenario_per_connection() {
+    let test_server = test_server(SCENARIOS_MAPPING).await;
+    let address = format!("127.0.0.1:{}", test_server.port);
+
+    for expected in [b"one", b"two"] {
+        let mut stream = TcpStream::connect(&address).await.unwrap();
+        let mut received = vec![0u8; 3];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, expected);
+    }
+
+    let mut stream = TcpStream::connect(&address).await.unwrap();
+    let mut received = vec![0u8; 2];
+    stream.write_all(b"hey").await.unwrap();
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"ho");
+}
+
+#[tokio::test]
+async fn test_tcp_server_falls_through_when_first_message_times_out() {
+    let mapping = SCENARIOS_MAPPING
+        .replace(
+            "    scenarios:",
+            "    actions:\n        - message: two\n          execute: Send\n\n    scenarios:",
+        )
+        .replace(
+            "scenario: greeter",
+            "scenario: greeter\n          first_message_timeout_ms: 200",
+        );
+    let test_server = test_server(&mapping).await;
+    let address = format!("127.0.0.1:{}", test_server.port);
+
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(&address).await.unwrap();
+        let mut received = vec![0u8; 3];
+        stream.read_exact(&mut received).await.unwrap();
+    }
+
+    // A client waiting for the server gets the top level scenario
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(&address).await.unwrap();
+    let mut received = vec![0u8; 3];
+    tokio::time::timeout(Duration::from_secs(2), stream.read_exact(&mut received))
+        .await
+        .expect("the first message selector did not time out")
+        .unwrap();
+    assert_eq!(received, b"two");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_tcp_server_coordinates_connections_with_signals() {
+    let test_server = test_server(SIGNALS_MAPPING).await;
+    let address = format!("127.0.0.1:{}", test_server.port);
+
+    let mut control = TcpStream::connect(&address).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    let mut data = TcpStream::connect(&address).await.unwrap();
+
+    let mut received = vec![0u8; 4];
+    let early = tokio::time::timeout(Duration::from_millis(200), data.read_exact(&mut received));
+    assert!(early.await.is_err());
+
+    control.write_all(b"sub").await.unwrap();
+    data.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"snap");
+}
+
+#[tokio::test]
+async fn test_tcp_server_broadcasts_to_group() {
+    let test_server = test_server(BROADCAST_MAPPING).await;
+    let address = format!("127.0.0.1:{}", test_server.port);
+
+    let mut subscribers = Vec::new();
+    for _ in 0..2 {
+        subscribers.push(TcpStream::connect(&address).await.unwrap());
+        sleep(Duration::from_millis(50)).await;
+    }
+    let mut publisher = TcpStream::connect(&address).await.unwrap();
+    publisher.write_all(b"go").await.unwrap();
+
+    for subscriber in subscribers.iter_mut() {
+        let mut received = vec![0u8; 4];
+        subscriber.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, b"tick");
+    }
+
+    let mut received = vec![0u8; 4];
+    let publisher_read = publisher.read(&mut received).await.unwrap();
+    assert_eq!(publisher_read, 0);
+}
+
+#[tokio::test]
+async fn test_tcp_server_reports_unfinished_actions_on_shutdown() {
+    let test_server = test_server(CHUNKED_MAPPING.replace("Send", "Recv").as_str()).await;
+
+    let _stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    sleep(Duration::from_millis(200)).await;
+    test_server.shutdown.notify_waiters();
+    test_server.server.await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(700));
+
+    let report = std::fs::read_to_string(test_server.report_file.path()).unwrap();
+    assert!(report.contains(r#"errors="1""#));
+    assert!(report.contains("<skipped/>"));
+    assert!(report.contains("interrupted by server shutdown"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_replaces_stale_report_without_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let report_path = dir.path().join("result.xml");
+    std::fs::write(
+        &report_path,
+        "<testsuites><testsuite name=\"stale\"/></testsuites>",
+    )
+    .unwrap();
+    let test_server = test_server_with(HELLO_MAPPING, |config| ServerConfig {
+        report_path: report_path.to_string_lossy().to_string(),
+        ..config
+    })
+    .await;
+
+    sleep(Duration::from_millis(100)).await;
+    test_server.shutdown.notify_waiters();
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::NoClient);
+    assert!(run.report_errors.is_empty());
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    assert!(report.contains("<testsuites"));
+    assert!(!report.contains("stale"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_fails_to_start_with_invalid_mapping() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mapping_file = create_mapping_file("name: [");
+    let report_file = tempfile::NamedTempFile::new().unwrap();
+
+    let config = test_config(&mapping_file, &report_file);
+    let started = run_tcp_server(listener, config, std::future::pending::<()>()).await;
+    let err = started.unwrap_err();
+    assert!(format!("{:#}", err).contains("error parsing the mapping file"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_fails_fast_with_outcome() {
+    let test_server = test_server_with(HELLO_MAPPING, |config| ServerConfig {
+        fail_fast: true,
+        ..config
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    stream.write_all(b"World").await.unwrap();
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not fail fast")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::Failed);
+    assert_eq!(run.outcome() as u8, 1);
+    assert_eq!((run.summary.connections, run.summary.failures), (1, 1));
+
+    let frame = run.connection(1).unwrap().journal.at_step(1).unwrap();
+    assert_eq!(frame.status, FrameStatus::Mismatched);
+    assert_eq!(frame.data, Bytes::from("World"));
+    assert_eq!(run.received("msg1"), 0);
+
+    let report = std::fs::read_to_string(test_server.report_file.path()).unwrap();
+    assert!(report.contains(r#"<testcase name="Shutdown" time="0"><skipped/>"#));
+    assert!(report.contains("first mismatch at offset 0 (0x0)"));
+    assert!(report.contains("48 65 6c 6c 6f"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_is_controlled_by_admin_api() {
+    let admin_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_port = admin_listener.local_addr().unwrap().port();
+    let test_server = test_server_with(ADMIN_MAPPING, |config| ServerConfig {
+        admin_listener: Some(admin_listener),
+        ..config
+    })
+    .await;
+    let port = test_server.port;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let mut received = vec![0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+
+    let (status, body) = admin_request(admin_port, "GET", "/connections", "").await;
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""index":1"#));
+    assert!(body.contains(r#""step":"msg2""#));
+    assert!(body.contains(r#""bytes_sent":5"#));
+
+    let (status, _) = admin_request(
+        admin_port,
+        "POST",
+        "/connections/1/send",
+        r#"{"message": "msg2"}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, _) = admin_request(
+        admin_port,
+        "POST",
+        "/connections/1/send",
+        r#"{"hex": "48 69"}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    let mut received = vec![0u8; 3];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"!Hi");
+
+    let (status, _) = admin_request(
+        admin_port,
+        "POST",
+        "/connections/9/send",
+        r#"{"hex": "21"}"#,
+    )
+    .await;
+    assert_eq!(status, 404);
+    let (status, body) = admin_request(admin_port, "PUT", "/mapping", "name: [").await;
+    assert_eq!(status, 400);
+    assert!(body.contains("error parsing the mapping file"));
+    let resized = format!("{}\n    receive_buffer: 4096\n", RELOADED_MAPPING);
+    let (status, body) = admin_request(admin_port, "PUT", "/mapping", &resized).await;
+    assert_eq!(status, 400);
+    assert!(body.contains("receive_buffer can not change"));
+    let (status, _) = admin_request(admin_port, "PUT", "/mapping", RELOADED_MAPPING).await;
+    assert_eq!(status, 200);
+
+    stream.write_all(b"!").await.unwrap();
+    drop(stream);
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        let (status, body) = admin_request(admin_port, "GET", "/results", "").await;
+        assert_eq!(status, 200);
+        let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+        if results["summary"]["connections"] == 1 {
+            assert_eq!(results["outcome"], "passed");
+            assert_eq!(results["connections"][0]["bytes_sent"], 8);
+            break;
+        }
+        assert!(Instant::now() < deadline, "connection did not finish");
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let (status, body) = admin_request(admin_port, "GET", "/connections/1/journal", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"[{"step":2,"message":"msg2","data":"21","status":"matched"}]"#
+    );
+    let (_, body) = admin_request(admin_port, "GET", "/verify", "").await;
+    assert_eq!(body, r#"{"received":{"msg2":1},"unexpected":0}"#);
+
+    // New connections follow the loaded mapping
+    let (status, _) = admin_request(admin_port, "POST", "/reset", "").await;
+    assert_eq!(status, 200);
+    write_to_server(port, &Bytes::from("Hello")).await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+    let (_, body) = admin_request(admin_port, "GET", "/results", "").await;
+    assert!(body.contains("reloaded #2"));
+    assert!(!body.contains("admin #1"));
+
+    let (status, _) = admin_request(admin_port, "POST", "/shutdown", "").await;
+    assert_eq!(status, 200);
+    tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_server_reloads_changed_mapping_for_new_connections() {
+    let test_server = test_server_with(RELOADED_MAPPING, |config| ServerConfig {
+        watch_mapping: true,
+        ..config
+    })
+    .await;
+    let (port, mapping_file) = (test_server.port, &test_server.mapping_file);
+
+    write_to_server(port, &Bytes::from("Hello")).await.unwrap();
+
+    // An invalid edit is rejected, keeping the previous mapping
+    std::fs::write(mapping_file.path(), "name: [").unwrap();
+    sleep(Duration::from_millis(200)).await;
+    write_to_server(port, &Bytes::from("Hello")).await.unwrap();
+
+    // A rewrite of the same size is told apart by its content
+    let rewritten = RELOADED_MAPPING.replace("reloaded", "rewrited");
+    std::fs::write(mapping_file.path(), &rewritten).unwrap();
+    sleep(Duration::from_millis(200)).await;
+    write_to_server(port, &Bytes::from("Hello")).await.unwrap();
+
+    std::fs::write(mapping_file.path(), ADMIN_MAPPING).unwrap();
+    sleep(Duration::from_millis(200)).await;
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))
+        .await
+        .unwrap();
+    let mut received = vec![0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, b"Hello");
+    stream.write_all(b"!").await.unwrap();
+    drop(stream);
+
+    sleep(Duration::from_millis(100)).await;
+    test_server.shutdown.notify_waiters();
+    let run = test_server.server.await.unwrap();
+    assert_eq!(run.outcome(), Outcome::Passed);
+    assert_eq!(run.received("msg1"), 3);
+
+    let report = std::fs::read_to_string(test_server.report_file.path()).unwrap();
+    assert!(report.contains("reloaded #2"));
+    assert!(report.contains("rewrited #3"));
+    assert!(report.contains("admin #4"));
+}
+
+#[tokio::test]
+async fn test_tcp_server_writes_ready_file_with_bound_address() {
+    let dir = tempfile::tempdir().unwrap();
+    let ready_file = dir.path().join("ready.json");
+    let test_server = test_server_with(HELLO_MAPPING, |config| ServerConfig {
+        ready_file: Some(ready_file.to_string_lossy().to_string()),
+        ..config
+    })
+    .await;
+
+    // No sleep needed, the file shows up once the server accepts connections
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !ready_file.exists() {
+        assert!(Instant::now() < deadline, "ready file was not written");
+        sleep(Duration::from_millis(10)).await;
+    }
+    let ready: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&ready_file).unwrap()).unwrap();
+    assert_eq!(ready["address"], format!("127.0.0.1:{}", test_server.port));
+
+    let address = ready["address"].as_str().unwrap().to_string();
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(b"Hello").await.unwrap();
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::Passed);
+    assert!(!ready_file.exists());
+}
+
+#[tokio::test]
+async fn test_tcp_server_measures_latency_to_arrival_of_reply() {
+    // The server takes its time before the Recv, the client replies at once
+    let mapping = ADMIN_MAPPING.replace(
+        "execute: Recv",
+        "execute: Recv\n          wait_for: 1\n        - execute: Shutdown",
+    );
+    let test_server = test_server(&mapping).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    let mut received = vec![0u8; 5];
+    stream.read_exact(&mut received).await.unwrap();
+    stream.write_all(b"!").await.unwrap();
+
+    let run = tokio::time::timeout(Duration::from_secs(3), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::Passed);
+    let latency = run.summary.latency.stats().unwrap();
+    assert_eq!(latency.count, 1);
+    assert!(latency.max_ms < 500.0, "latency was {} ms", latency.max_ms);
+}
+
+#[tokio::test]
+async fn test_tcp_server_journals_bytes_left_unconsumed() {
+    let test_server = test_server(HELLO_MAPPING).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    stream.write_all(b"Hello!!").await.unwrap();
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    let journal = &run.connection(1).unwrap().journal;
+    assert_eq!(run.received("msg1"), 1);
+    let unexpected: Vec<_> = journal.unexpected().collect();
+    assert_eq!(unexpected.len(), 1);
+    assert_eq!(unexpected[0].data, Bytes::from("!!"));
+    assert_eq!(unexpected[0].status, FrameStatus::Unmatched);
+}
+
+#[tokio::test]
+async fn test_tcp_server_journals_a_mismatched_frame_once() {
+    let mapping = HELLO_MAPPING.replace(
+        "        - execute: Shutdown",
+        "        - message: msg2\n          execute: Recv\n        - execute: Shutdown",
+    );
+    let mapping = mapping.replace("    actions:", "        msg2: \"World\"\n\n    actions:");
+    let test_server = test_server(&mapping).await;
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", test_server.port))
+        .await
+        .unwrap();
+    stream.write_all(b"Junk!").await.unwrap();
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.summary.failures, 2);
+    let journal = &run.connection(1).unwrap().journal;
+    let unexpected: Vec<_> = journal.unexpected().collect();
+    assert_eq!(unexpected.len(), 1);
+    assert_eq!(unexpected[0].data, Bytes::from("Junk!"));
+    assert_eq!(unexpected[0].status, FrameStatus::Mismatched);
+}
+
+#[tokio::test]
+async fn test_tcp_server_blocks_client_writes_while_reads_are_paused() {
+    let message = "A".repeat(256 * 1024);
+    let mapping = format!(
+        r#"
+    name: paused
+
+    receive_buffer: 4096
+
+    messages:
+        msg1: "{}"
+
+    actions:
+        - execute: PauseRead
+          duration_ms: 500
+        - message: msg1
+          execute: Recv
+        - execute: Shutdown
+"#,
+        message
+    );
+    let test_server = test_server(&mapping).await;
+
+    let mut stream = connect_with_small_send_buffer(test_server.port).await;
+    let mut write = Box::pin(stream.write_all(message.as_bytes()));
+
+    let blocked = tokio::time::timeout(Duration::from_millis(300), &mut write).await;
+    assert!(
+        blocked.is_err(),
+        "the write completed while reads were paused"
+    );
+    tokio::time::timeout(Duration::from_secs(2), write)
+        .await
+        .expect("the write did not complete once reads resumed")
+        .unwrap();
+    drop(stream);
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::Passed);
+    assert_eq!(run.received("msg1"), 1);
+}
+
+#[tokio::test]
+async fn test_tcp_server_keeps_reads_paused_until_the_next_action_reading() {
+    let message = "A".repeat(256 * 1024);
+    let mapping = format!(
+        r#"
+    name: paused
+
+    receive_buffer: 4096
+
+    messages:
+        msg1: "{}"
+
+    actions:
+        - execute: PauseRead
+        - execute: WaitFor
+          signal: drain
+          duration_ms: 5000
+        - message: msg1
+          execute: Recv
+        - execute: Shutdown
+
+    scenarios:
+        drainer:
+            actions:
+                - execute: Signal
+                  signal: drain
+
+    select:
+        - connection: 2
+          scenario: drainer
+"#,
+        message
+    );
+    let test_server = test_server(&mapping).await;
+
+    let mut stream = connect_with_small_send_buffer(test_server.port).await;
+    let mut write = Box::pin(stream.write_all(message.as_bytes()));
+
+    let blocked = tokio::time::timeout(Duration::from_millis(300), &mut write).await;
+    assert!(
+        blocked.is_err(),
+        "the write completed while reads were paused"
+    );
+    // Reads resume once the other connection lets the WaitFor through
+    write_to_server(test_server.port, &Bytes::new())
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(2), write)
+        .await
+        .expect("the write did not complete once reads resumed")
+        .unwrap();
+    drop(stream);
+
+    let run = tokio::time::timeout(Duration::from_secs(2), test_server.server)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+    assert_eq!(run.outcome(), Outcome::Passed);
+    assert_eq!(run.received("msg1"), 1);
+}